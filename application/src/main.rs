@@ -1,11 +1,339 @@
+use clap::Parser;
 use std::error::Error;
-use ws_socket::start;
+use std::process;
+use std::time::Duration;
+use ws_socket::{
+    check_pairs, read_cache, run_cache, Aggregation, Channels, CollectorConfig, Decimal,
+    HeartbeatConfig, OutlierFilter, PairState, PairValidation, ReconnectConfig, TradeSide,
+    WSResult,
+};
+
+#[derive(Parser, Debug)]
+#[clap(author = "Nizamuddin", version, about)]
+/// Web socket argument structure
+pub struct Args {
+    /// Mode should be cache or read, cache collect pairs data and read show the cached data
+    #[clap(short, long)]
+    pub mode: String,
+
+    /// Pairs should collect coins with pair
+    #[clap(short, long, default_value = "")]
+    pub pairs: String,
+
+    /// How long cache mode collects data, ex. 30s or 5m
+    #[clap(short, long, default_value = "10s", value_parser = humantime::parse_duration)]
+    pub duration: Duration,
+
+    /// File cache mode writes the collected data to
+    #[clap(short, long, default_value = "exchanges.json")]
+    pub output: String,
+
+    /// Web socket configs file
+    #[clap(short, long, default_value = "ws_details.json")]
+    pub config: String,
+
+    /// Reconnect attempts per exchange before it gives up
+    #[clap(long, default_value_t = 5)]
+    pub max_reconnects: u32,
+
+    /// Idle time before a socket is considered stale and reconnected, ex. 60s
+    #[clap(long, default_value = "60s", value_parser = humantime::parse_duration)]
+    pub stale_timeout: Duration,
+
+    /// Aggregation of pair prices: mean, median, trimmed:<percent>, twap, last, mid or microprice
+    #[clap(short, long, default_value = "mean")]
+    pub aggregate: Aggregation,
+
+    /// Outlier filter before aggregation: none, mad:<threshold> or pct:<percent>
+    #[clap(long, default_value = "none")]
+    pub outliers: OutlierFilter,
+
+    /// Exit with an error status when a pair ends without data
+    #[clap(long)]
+    pub require_data: bool,
+
+    /// Resample prices into OHLC bars of this interval, ex. 1s, 5s, 1m
+    #[clap(long, value_parser = humantime::parse_duration)]
+    pub bars: Option<Duration>,
+
+    /// Subscribe order books and keep this many levels per side, ex. 10
+    #[clap(long)]
+    pub depth: Option<usize>,
+
+    /// Subscribe public trades and keep every trade in the cache file
+    #[clap(long)]
+    pub trades: bool,
+
+    /// Subscribe candles of this interval and keep the closed ones, ex. 1m, 1h
+    #[clap(long, value_parser = humantime::parse_duration)]
+    pub kline: Option<Duration>,
+
+    /// Quote coins that stand in for each other, ex. usd,usdt,usdc, can be repeated
+    #[clap(long)]
+    pub quote_group: Vec<String>,
+
+    /// Convert prices listed with an equivalent quote at the live rate
+    #[clap(long)]
+    pub convert_quotes: bool,
+
+    /// Pairs derived from two legs against the bridge coin, ex. sol_eth from sol_usdt and eth_usdt
+    #[clap(long, default_value = "")]
+    pub synthetic: String,
+
+    /// Coin both legs of a synthetic pair are quoted in
+    #[clap(long, default_value = "usdt")]
+    pub bridge: String,
+
+    /// Legs older than this are not combined into a synthetic price
+    #[clap(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    pub max_leg_staleness: Duration,
+
+    /// Check of the pairs against the instrument lists of the exchanges: off, warn or reject
+    #[clap(long, default_value = "warn")]
+    pub validate_pairs: PairValidation,
+
+    /// File the instrument lists of the exchanges are cached in
+    #[clap(long, default_value = "instruments.json")]
+    pub instruments: String,
+
+    /// How long cached instrument lists are used before they are fetched again
+    #[clap(long, default_value = "24h", value_parser = humantime::parse_duration)]
+    pub instruments_ttl: Duration,
+
+    /// File read mode shows the cached data from
+    #[clap(short, long, default_value = "exchanges.json")]
+    pub input: String,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let r = start().await;
-    if r.is_err() {
-        println!("Error: {:?}", r.unwrap_err());
+    if let Err(e) = start(Args::parse()).await {
+        println!("Error: {:?}", e);
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// map arguments onto the collector and start execution
+async fn start(args: Args) -> WSResult<()> {
+    let mode: String = args.mode;
+
+    if mode == "cache" {
+        // get pairs from the argument
+        let pairs: String = args.pairs;
+
+        let split = |pairs: &str| -> Vec<String> {
+            pairs
+                .split(',')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| pair.to_string())
+                .collect()
+        };
+
+        if !pairs.is_empty() || !args.synthetic.is_empty() {
+            let requested = split(&pairs)
+                .into_iter()
+                .chain(split(&args.synthetic))
+                .collect::<Vec<String>>()
+                .join(",");
+            if check_pairs(&requested) {
+                let config = CollectorConfig {
+                    pairs: split(&pairs),
+                    synthetic_pairs: split(&args.synthetic),
+                    bridge: args.bridge.to_uppercase(),
+                    max_leg_staleness: args.max_leg_staleness,
+                    handle_signals: true,
+                    pair_validation: args.validate_pairs,
+                    instruments_path: args.instruments,
+                    instruments_ttl: args.instruments_ttl,
+                    duration: args.duration,
+                    output_path: args.output,
+                    config_path: args.config,
+                    aggregation: args.aggregate,
+                    outlier_filter: args.outliers,
+                    require_data: args.require_data,
+                    bar_interval: args.bars,
+                    channels: Channels {
+                        depth: args.depth,
+                        trades: args.trades,
+                        kline: args.kline,
+                        ..Default::default()
+                    },
+                    quote_groups: args
+                        .quote_group
+                        .iter()
+                        .map(|group| group.split(',').map(|coin| coin.to_uppercase()).collect())
+                        .collect(),
+                    convert_quotes: args.convert_quotes,
+                    reconnect: ReconnectConfig {
+                        max_attempts: args.max_reconnects,
+                        ..Default::default()
+                    },
+                    heartbeat: HeartbeatConfig {
+                        stale_timeout: args.stale_timeout,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                run_cache(&config).await?;
+            }
+        } else {
+            println!("Pairs is required");
+        }
+    } else if mode == "read" {
+        let cache = read_cache(&args.input)?;
+
+        for (key, pari_cache) in &cache.pairs {
+            match pari_cache.aggregate {
+                Some(aggregate) if pari_cache.state == PairState::Ok => println!(
+                    "pair: {:?} -> aggregate ({}): {}, vwap: {:?}",
+                    key, pari_cache.aggregation, aggregate, pari_cache.vwap
+                ),
+                None if pari_cache.state == PairState::Ok => println!(
+                    "pair: {:?} -> no aggregate ({}) without quotes, vwap: {:?}",
+                    key, pari_cache.aggregation, pari_cache.vwap
+                ),
+                _ => println!("pair: {:?} -> no data", key),
+            }
+            if !pari_cache.missing.is_empty() {
+                println!("    no data from: {}", pari_cache.missing.join(", "));
+            }
+            for price in &pari_cache.prices {
+                if let Some(reason) = &price.rejected {
+                    println!(
+                        "    rejected {} from {}: {}",
+                        price.price, price.name, reason
+                    );
+                }
+            }
+            let mut listings = vec![];
+            for price in &pari_cache.prices {
+                if let Some(instrument) = price.instrument.as_ref().filter(|i| &i.key() != key) {
+                    let listing = format!(
+                        "    {} listed as {}{}",
+                        price.name,
+                        instrument,
+                        if price.rate.is_some() {
+                            ", converted"
+                        } else {
+                            ""
+                        }
+                    );
+                    if !listings.contains(&listing) {
+                        listings.push(listing);
+                    }
+                }
+            }
+            for listing in listings {
+                println!("{listing}");
+            }
+            if let Some(synthetic) = &pari_cache.synthetic {
+                let count = pari_cache
+                    .prices
+                    .iter()
+                    .filter(|price| price.legs.is_some())
+                    .count();
+                println!(
+                    "    synthetic from {} / {}: {} prices",
+                    synthetic.base_leg, synthetic.quote_leg, count
+                );
+            }
+            let mut names: Vec<&String> = pari_cache.by_exchange.keys().collect();
+            names.sort();
+            for name in names {
+                let stats = &pari_cache.by_exchange[name];
+                println!(
+                    "    {}: count: {}, mean: {}, min: {}, max: {}, last: {}, stddev: {}",
+                    name, stats.count, stats.mean, stats.min, stats.max, stats.last, stats.stddev
+                );
+            }
+            if let Some(spread) = &pari_cache.spread {
+                println!(
+                    "    spread: {} ({:.2} bps) between {} and {}",
+                    spread.absolute, spread.bps, spread.high, spread.low
+                );
+            }
+            if let Some(bbo) = &pari_cache.bbo {
+                println!(
+                    "    bbo: bid {} ({:?}) on {}, ask {} ({:?}) on {}",
+                    bbo.bid,
+                    bbo.bid_size,
+                    bbo.bid_exchange,
+                    bbo.ask,
+                    bbo.ask_size,
+                    bbo.ask_exchange
+                );
+            }
+            if !pari_cache.trades.is_empty() {
+                let volume = |side: TradeSide| -> Decimal {
+                    pari_cache
+                        .trades
+                        .iter()
+                        .filter(|trade| trade.side == side)
+                        .map(|trade| trade.size)
+                        .sum()
+                };
+                println!(
+                    "    trades: {}, buy volume: {}, sell volume: {}",
+                    pari_cache.trades.len(),
+                    volume(TradeSide::Buy),
+                    volume(TradeSide::Sell)
+                );
+            }
+            if let Some(depth) = &pari_cache.depth {
+                println!("    depth of {} exchanges:", depth.by_exchange.len());
+                for (bid, ask) in depth.bids.iter().zip(&depth.asks) {
+                    println!(
+                        "        bid {} x {} | ask {} x {}",
+                        bid.price, bid.size, ask.price, ask.size
+                    );
+                }
+            }
+            if let Some(bars) = &pari_cache.bars {
+                println!("    bars every {}ms:", bars.interval_ms);
+                for bar in &bars.composite {
+                    println!(
+                        "        {}: open: {}, high: {}, low: {}, close: {}, volume: {}, ticks: {}",
+                        bar.start, bar.open, bar.high, bar.low, bar.close, bar.volume, bar.ticks
+                    );
+                }
+            }
+            if !pari_cache.candles.is_empty() {
+                println!("    candles:");
+                for candle in &pari_cache.candles {
+                    println!(
+                        "        {} {}: open: {}, high: {}, low: {}, close: {}, volume: {}{}",
+                        candle.exchange,
+                        candle.start,
+                        candle.open,
+                        candle.high,
+                        candle.low,
+                        candle.close,
+                        candle.volume,
+                        if candle.resampled { " (resampled)" } else { "" }
+                    );
+                }
+            }
+        }
+        for (name, status) in &cache.exchanges {
+            match &status.reason {
+                Some(reason) => {
+                    println!("exchange: {:?} -> {:?} ({})", name, status.status, reason)
+                }
+                None => println!("exchange: {:?} -> {:?}", name, status.status),
+            }
+        }
+        if cache.interrupted {
+            println!("run was interrupted before the duration elapsed");
+        }
+        for reconnect in &cache.reconnects {
+            println!(
+                "reconnect: {:?} -> {:?} after {} attempts ({})",
+                reconnect.name, reconnect.reconnected_at, reconnect.attempts, reconnect.reason
+            );
+        }
+    } else {
+        println!("Invalid mode");
     }
     Ok(())
 }
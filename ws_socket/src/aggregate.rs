@@ -0,0 +1,489 @@
+use std::{collections::HashMap, fmt, num::NonZeroU64, str::FromStr};
+
+use rust_decimal::{prelude::FromPrimitive, Decimal, MathematicalOps};
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Bar, Bars, Bbo, Candle, ExchangeStats, PricesPairs, Quote, Spread, Trade};
+
+/// 0.6745, scales the median absolute deviation to a normal standard deviation
+const MAD_SCALE: Decimal = Decimal::from_parts(6745, 0, 0, false, 4);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "method", rename_all = "lowercase")]
+/// aggregation method of the pair prices
+pub enum Aggregation {
+    /// arithmetic mean of every data point
+    #[default]
+    Mean,
+    Median,
+    /// mean without the given percent of lowest and highest prices
+    Trimmed {
+        percent: f64,
+    },
+    /// time weighted average, every price weighted by how long it stood
+    Twap,
+    /// last received price
+    Last,
+    /// mean of the quote mid prices
+    Mid,
+    /// mean of the quote size weighted mid prices
+    Microprice,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "method", rename_all = "lowercase")]
+/// outlier filter applied before aggregation, non-positive prices are always rejected
+pub enum OutlierFilter {
+    #[default]
+    None,
+    /// reject points whose modified z-score around the cross-exchange median is above threshold
+    Mad { threshold: f64 },
+    /// reject points deviating more than percent from the cross-exchange median
+    Percent { percent: f64 },
+}
+
+impl FromStr for OutlierFilter {
+    type Err = String;
+
+    /// parse `none`, `mad:3.5` or `pct:2`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (method, param) = match s.split_once(':') {
+            Some((method, param)) => (method, Some(param)),
+            None => (s, None),
+        };
+        let value = param.map(|param| param.parse::<f64>());
+        match (method, value) {
+            ("none", None) => Ok(OutlierFilter::None),
+            ("mad", Some(Ok(threshold))) if threshold > 0.0 => Ok(OutlierFilter::Mad { threshold }),
+            ("pct", Some(Ok(percent))) if percent > 0.0 => Ok(OutlierFilter::Percent { percent }),
+            _ => Err(format!(
+                "unknown outlier filter {s}, expected none, mad:<threshold> or pct:<percent>"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutlierFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutlierFilter::None => write!(f, "none"),
+            OutlierFilter::Mad { threshold } => write!(f, "mad:{threshold}"),
+            OutlierFilter::Percent { percent } => write!(f, "pct:{percent}"),
+        }
+    }
+}
+
+impl OutlierFilter {
+    /// mark outliers rejected with the reason, rejected points stay in the prices
+    pub fn reject(&self, prices: &mut [PricesPairs]) {
+        for price in prices.iter_mut() {
+            if price.rejected.is_none() && price.price <= Decimal::ZERO {
+                price.rejected = Some("non-positive price".to_string());
+            }
+        }
+
+        // every exchange gets one vote, a chatty or off-market venue can not move the center
+        let mut by_exchange: HashMap<&str, Vec<Decimal>> = HashMap::new();
+        for price in prices.iter().filter(|p| p.rejected.is_none()) {
+            by_exchange
+                .entry(&price.name)
+                .or_default()
+                .push(price.price);
+        }
+        let Some(center) = median(
+            by_exchange
+                .values()
+                .filter_map(|prices| median(prices.clone()))
+                .collect(),
+        ) else {
+            return;
+        };
+
+        match *self {
+            OutlierFilter::None => {}
+            OutlierFilter::Mad { threshold } => {
+                // deviations weigh one over the price count of their exchange
+                let deviations = by_exchange
+                    .values()
+                    .flat_map(|prices| {
+                        let weight = Decimal::ONE / Decimal::from(prices.len());
+                        prices.iter().map(move |p| ((p - center).abs(), weight))
+                    })
+                    .collect();
+                let mad = weighted_median(deviations).unwrap_or_default();
+                // identical prices leave no spread to measure against
+                if mad.is_zero() {
+                    return;
+                }
+                let limit = Decimal::from_f64(threshold).unwrap_or(Decimal::MAX);
+                for price in prices.iter_mut().filter(|p| p.rejected.is_none()) {
+                    let z_score = MAD_SCALE * (price.price - center).abs() / mad;
+                    if z_score > limit {
+                        price.rejected = Some(format!(
+                            "modified z-score {z_score:.2} above {threshold} around median {center}"
+                        ));
+                    }
+                }
+            }
+            OutlierFilter::Percent { percent } => {
+                let limit = Decimal::from_f64(percent).unwrap_or(Decimal::MAX);
+                for price in prices.iter_mut().filter(|p| p.rejected.is_none()) {
+                    let deviation = (price.price - center).abs() / center * Decimal::ONE_HUNDRED;
+                    if deviation > limit {
+                        price.rejected = Some(format!(
+                            "deviation {deviation:.2}% above {percent}% from median {center}"
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Aggregation {
+    type Err = String;
+
+    /// parse `mean`, `median`, `trimmed:5`, `twap` or `last`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (method, param) = match s.split_once(':') {
+            Some((method, param)) => (method, Some(param)),
+            None => (s, None),
+        };
+        match (method, param) {
+            ("mean", None) => Ok(Aggregation::Mean),
+            ("median", None) => Ok(Aggregation::Median),
+            ("twap", None) => Ok(Aggregation::Twap),
+            ("last", None) => Ok(Aggregation::Last),
+            ("mid", None) => Ok(Aggregation::Mid),
+            ("microprice", None) => Ok(Aggregation::Microprice),
+            ("trimmed", Some(percent)) => match percent.parse::<f64>() {
+                Ok(percent) if (0.0..50.0).contains(&percent) => {
+                    Ok(Aggregation::Trimmed { percent })
+                }
+                _ => Err(format!("trimmed percent {percent} should be in 0..50")),
+            },
+            _ => Err(format!(
+                "unknown aggregation {s}, expected mean, median, trimmed:<percent>, twap, last, mid or microprice"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Aggregation::Mean => write!(f, "mean"),
+            Aggregation::Median => write!(f, "median"),
+            Aggregation::Trimmed { percent } => write!(f, "trimmed:{percent}"),
+            Aggregation::Twap => write!(f, "twap"),
+            Aggregation::Last => write!(f, "last"),
+            Aggregation::Mid => write!(f, "mid"),
+            Aggregation::Microprice => write!(f, "microprice"),
+        }
+    }
+}
+
+impl Aggregation {
+    /// aggregate the prices, `end` is the monotonic receive time the last price stood until
+    pub fn aggregate(&self, prices: &[PricesPairs], end: u64) -> Option<Decimal> {
+        if prices.is_empty() {
+            return None;
+        }
+        match self {
+            Aggregation::Mean => Some(mean(prices.iter().map(|p| p.price))),
+            Aggregation::Median => median(prices.iter().map(|p| p.price).collect()),
+            Aggregation::Trimmed { percent } => {
+                let sorted = sorted_prices(prices);
+                let trim = (sorted.len() as f64 * percent / 100.0).floor() as usize;
+                Some(mean(sorted[trim..sorted.len() - trim].iter().copied()))
+            }
+            Aggregation::Twap => twap(prices, end),
+            Aggregation::Last => prices
+                .iter()
+                .enumerate()
+                .max_by_key(|(index, p)| (p.received_mono, *index))
+                .map(|(_, p)| p.price),
+            Aggregation::Mid => mean_some(prices.iter().filter_map(|p| p.quote.map(|q| q.mid()))),
+            Aggregation::Microprice => mean_some(
+                prices
+                    .iter()
+                    .filter_map(|p| p.quote.and_then(|q| q.microprice())),
+            ),
+        }
+    }
+}
+
+impl Quote {
+    /// middle of bid and ask
+    pub fn mid(&self) -> Decimal {
+        (self.bid + self.ask) / Decimal::TWO
+    }
+
+    /// mid weighted towards the side with less size, none without sizes
+    pub fn microprice(&self) -> Option<Decimal> {
+        let (bid_size, ask_size) = (self.bid_size?, self.ask_size?);
+        (self.bid * ask_size + self.ask * bid_size).checked_div(bid_size + ask_size)
+    }
+}
+
+/// volume weighted average price of the trades, of the last trades the ticker reported when
+/// no trades were collected
+pub fn vwap(prices: &[PricesPairs], trades: &[Trade]) -> Option<Decimal> {
+    if !trades.is_empty() {
+        let amount: Decimal = trades.iter().map(|trade| trade.price * trade.size).sum();
+        let volume: Decimal = trades.iter().map(|trade| trade.size).sum();
+        return amount.checked_div(volume);
+    }
+
+    // tickers repeat the last trade in every snapshot, it is counted once per exchange
+    let mut last_trade: HashMap<&str, (Decimal, Decimal)> = HashMap::new();
+    let (mut amount, mut volume) = (Decimal::ZERO, Decimal::ZERO);
+    for price in prices {
+        let Some(size) = price.last_size else {
+            continue;
+        };
+        if last_trade.insert(&price.name, (price.price, size)) == Some((price.price, size)) {
+            continue;
+        }
+        amount += price.price * size;
+        volume += size;
+    }
+    amount.checked_div(volume)
+}
+
+/// best bid and lowest ask over the latest quote of every exchange
+pub fn bbo(prices: &[PricesPairs]) -> Option<Bbo> {
+    let mut latest: HashMap<&str, (u64, usize, Quote)> = HashMap::new();
+    for (index, price) in prices.iter().enumerate() {
+        if let Some(quote) = price.quote {
+            let entry = latest
+                .entry(&price.name)
+                .or_insert((price.received_mono, index, quote));
+            if (price.received_mono, index) >= (entry.0, entry.1) {
+                *entry = (price.received_mono, index, quote);
+            }
+        }
+    }
+
+    let (bid_exchange, (_, _, bid)) = latest.iter().max_by_key(|(_, (_, _, q))| q.bid)?;
+    let (ask_exchange, (_, _, ask)) = latest.iter().min_by_key(|(_, (_, _, q))| q.ask)?;
+    Some(Bbo {
+        bid: bid.bid,
+        bid_size: bid.bid_size,
+        bid_exchange: bid_exchange.to_string(),
+        ask: ask.ask,
+        ask_size: ask.ask_size,
+        ask_exchange: ask_exchange.to_string(),
+    })
+}
+
+/// price statistics of every exchange in the prices
+pub fn exchange_stats(prices: &[PricesPairs]) -> HashMap<String, ExchangeStats> {
+    let mut grouped: HashMap<&str, Vec<&PricesPairs>> = HashMap::new();
+    for price in prices {
+        grouped.entry(&price.name).or_default().push(price);
+    }
+
+    grouped
+        .into_iter()
+        .map(|(name, points)| {
+            let count = points.len();
+            let mean = mean(points.iter().map(|p| p.price));
+            let variance = points
+                .iter()
+                .map(|p| (p.price - mean) * (p.price - mean))
+                .sum::<Decimal>()
+                / Decimal::from(count);
+            let last = points
+                .iter()
+                .enumerate()
+                .max_by_key(|(index, p)| (p.received_mono, *index))
+                .map(|(_, p)| p.price)
+                .unwrap_or(mean);
+
+            let stats = ExchangeStats {
+                count,
+                mean,
+                min: points.iter().map(|p| p.price).min().unwrap_or(mean),
+                max: points.iter().map(|p| p.price).max().unwrap_or(mean),
+                last,
+                stddev: variance.sqrt().unwrap_or_default(),
+            };
+            (name.to_string(), stats)
+        })
+        .collect()
+}
+
+/// spread between the highest and lowest exchange mean, none below two exchanges
+pub fn spread(stats: &HashMap<String, ExchangeStats>) -> Option<Spread> {
+    if stats.len() < 2 {
+        return None;
+    }
+    let (high, high_stats) = stats.iter().max_by_key(|(_, stats)| stats.mean)?;
+    let (low, low_stats) = stats.iter().min_by_key(|(_, stats)| stats.mean)?;
+
+    let absolute = high_stats.mean - low_stats.mean;
+    let mid = (high_stats.mean + low_stats.mean) / Decimal::TWO;
+    Some(Spread {
+        high: high.to_string(),
+        low: low.to_string(),
+        absolute,
+        bps: absolute.checked_div(mid).unwrap_or_default() * Decimal::from(10_000),
+    })
+}
+
+/// resample prices into OHLC bars per exchange and across exchanges
+pub fn bars(prices: &[PricesPairs], interval_ms: NonZeroU64) -> Bars {
+    let mut grouped: HashMap<&str, Vec<&PricesPairs>> = HashMap::new();
+    for price in prices {
+        grouped.entry(&price.name).or_default().push(price);
+    }
+
+    Bars {
+        interval_ms: interval_ms.get(),
+        composite: resample(prices.iter().collect(), interval_ms),
+        by_exchange: grouped
+            .into_iter()
+            .map(|(name, points)| (name.to_string(), resample(points, interval_ms)))
+            .collect(),
+    }
+}
+
+/// candles of one exchange resampled from its prices, only intervals inside the
+/// `(start, end)` window are complete and kept
+pub fn resampled_candles(
+    prices: &[PricesPairs],
+    pair: &str,
+    interval_ms: NonZeroU64,
+    window: (u64, u64),
+) -> Vec<Candle> {
+    let Some(exchange) = prices.first().map(|price| price.name.clone()) else {
+        return vec![];
+    };
+    resample(prices.iter().collect(), interval_ms)
+        .into_iter()
+        .filter(|bar| bar.start >= window.0 && bar.start + interval_ms.get() <= window.1)
+        .map(|bar| Candle {
+            exchange: exchange.clone(),
+            pair: pair.to_string(),
+            start: bar.start,
+            interval_ms: interval_ms.get(),
+            open: bar.open,
+            high: bar.high,
+            low: bar.low,
+            close: bar.close,
+            volume: bar.volume,
+            closed: true,
+            resampled: true,
+        })
+        .collect()
+}
+
+/// roll prices into bars, prices are placed by event time and fall back to receive time
+fn resample(mut prices: Vec<&PricesPairs>, interval_ms: NonZeroU64) -> Vec<Bar> {
+    let interval_ms = interval_ms.get();
+    let time = |price: &PricesPairs| price.event_time.unwrap_or(price.received_at);
+    prices.sort_by_key(|p| (time(p), p.received_mono));
+
+    let mut bars: Vec<Bar> = vec![];
+    for price in prices {
+        let start = time(price) - time(price) % interval_ms;
+        let volume = price.last_size.unwrap_or_default();
+        match bars.last_mut() {
+            Some(bar) if bar.start == start => {
+                bar.high = bar.high.max(price.price);
+                bar.low = bar.low.min(price.price);
+                bar.close = price.price;
+                bar.volume += volume;
+                bar.ticks += 1;
+            }
+            _ => bars.push(Bar {
+                start,
+                open: price.price,
+                high: price.price,
+                low: price.price,
+                close: price.price,
+                volume,
+                ticks: 1,
+            }),
+        }
+    }
+    bars
+}
+
+/// arithmetic mean
+fn mean(prices: impl Iterator<Item = Decimal>) -> Decimal {
+    let (amount, count) = prices.fold((Decimal::ZERO, 0u64), |(amount, count), price| {
+        (amount + price, count + 1)
+    });
+    amount.checked_div(Decimal::from(count)).unwrap_or_default()
+}
+
+/// arithmetic mean, none without values
+fn mean_some(values: impl Iterator<Item = Decimal>) -> Option<Decimal> {
+    let values: Vec<Decimal> = values.collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(mean(values.into_iter()))
+    }
+}
+
+/// median of the values
+fn median(mut values: Vec<Decimal>) -> Option<Decimal> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort();
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        Some((values[middle - 1] + values[middle]) / Decimal::TWO)
+    } else {
+        Some(values[middle])
+    }
+}
+
+/// median of `(value, weight)` pairs, the value where half of the total weight is reached
+fn weighted_median(mut values: Vec<(Decimal, Decimal)>) -> Option<Decimal> {
+    values.sort();
+    let half = values.iter().map(|(_, weight)| weight).sum::<Decimal>() / Decimal::TWO;
+    let mut cumulative = Decimal::ZERO;
+    for (value, weight) in values {
+        cumulative += weight;
+        if cumulative >= half {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// prices sorted ascending
+fn sorted_prices(prices: &[PricesPairs]) -> Vec<Decimal> {
+    let mut sorted: Vec<Decimal> = prices.iter().map(|p| p.price).collect();
+    sorted.sort();
+    sorted
+}
+
+/// time weighted average over receive times, the last price stands until `end`
+fn twap(prices: &[PricesPairs], end: u64) -> Option<Decimal> {
+    let mut ordered: Vec<&PricesPairs> = prices.iter().collect();
+    ordered.sort_by_key(|p| p.received_mono);
+
+    let mut amount = Decimal::ZERO;
+    let mut duration = Decimal::ZERO;
+    for (index, price) in ordered.iter().enumerate() {
+        let until = ordered
+            .get(index + 1)
+            .map(|next| next.received_mono)
+            .unwrap_or(end.max(price.received_mono));
+        let stood = Decimal::from(until - price.received_mono);
+        amount += price.price * stood;
+        duration += stood;
+    }
+
+    if duration > Decimal::ZERO {
+        Some(amount / duration)
+    } else {
+        ordered.last().map(|p| p.price)
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+
+use crate::{
+    errors::WSError,
+    types::{BookAction, BookDepth, BookUpdate, Depth, DepthLevel, OrderBook, WSResult},
+};
+
+impl OrderBook {
+    /// apply a book message, checking it follows the sequence of the book
+    ///
+    /// returns false when the update is older than the book and was skipped
+    pub fn apply(&mut self, update: &BookUpdate) -> WSResult<bool> {
+        if update.action == BookAction::Snapshot {
+            self.bids.clear();
+            self.asks.clear();
+            set_levels(&mut self.bids, &update.bids);
+            set_levels(&mut self.asks, &update.asks);
+            self.sequence = update.sequence;
+            return Ok(true);
+        }
+
+        if let (Some(sequence), Some(last)) = (update.sequence, self.sequence) {
+            // binance diffs covering only already applied updates are dropped
+            if update.first_sequence.is_some() && sequence <= last {
+                return Ok(false);
+            }
+            // binance `U` must not skip past the next sequence number
+            if let Some(first) = update.first_sequence {
+                if first > last + 1 {
+                    return Err(WSError::BookOutOfSync(format!(
+                        "{} expected update {} but got {first}",
+                        update.pair,
+                        last + 1
+                    )));
+                }
+            }
+        }
+        // okx `prevSeqId` must be the sequence of the book
+        if let Some(prev) = update.prev_sequence {
+            if Some(prev) != self.sequence {
+                return Err(WSError::BookOutOfSync(format!(
+                    "{} previous sequence {prev} does not follow {:?}",
+                    update.pair, self.sequence
+                )));
+            }
+        }
+
+        set_levels(&mut self.bids, &update.bids);
+        set_levels(&mut self.asks, &update.asks);
+        if update.sequence.is_some() {
+            self.sequence = update.sequence;
+        }
+        Ok(true)
+    }
+
+    /// bids from the highest price
+    pub fn best_bids(&self) -> impl Iterator<Item = DepthLevel> + '_ {
+        self.bids.iter().rev().map(|(price, size)| DepthLevel {
+            price: *price,
+            size: *size,
+        })
+    }
+
+    /// asks from the lowest price
+    pub fn best_asks(&self) -> impl Iterator<Item = DepthLevel> + '_ {
+        self.asks.iter().map(|(price, size)| DepthLevel {
+            price: *price,
+            size: *size,
+        })
+    }
+
+    /// top levels of the book
+    pub fn depth(&self, levels: usize, updated_at: u64) -> BookDepth {
+        BookDepth {
+            bids: self.best_bids().take(levels).collect(),
+            asks: self.best_asks().take(levels).collect(),
+            sequence: self.sequence,
+            updated_at,
+        }
+    }
+}
+
+impl BookDepth {
+    /// divide the level prices by the rate of an equivalent quote
+    pub fn convert(&mut self, rate: Decimal) {
+        for level in self.bids.iter_mut().chain(self.asks.iter_mut()) {
+            level.price = level.price.checked_div(rate).unwrap_or_default();
+        }
+    }
+}
+
+impl Depth {
+    /// merge the exchange books by price, keeping as many levels as the deepest book
+    pub fn consolidate(&mut self) {
+        let levels = self
+            .by_exchange
+            .values()
+            .map(|book| book.bids.len().max(book.asks.len()))
+            .max()
+            .unwrap_or_default();
+
+        let mut bids: BTreeMap<Decimal, Decimal> = BTreeMap::new();
+        let mut asks: BTreeMap<Decimal, Decimal> = BTreeMap::new();
+        for book in self.by_exchange.values() {
+            for level in &book.bids {
+                *bids.entry(level.price).or_default() += level.size;
+            }
+            for level in &book.asks {
+                *asks.entry(level.price).or_default() += level.size;
+            }
+        }
+
+        let level = |(price, size): (&Decimal, &Decimal)| DepthLevel {
+            price: *price,
+            size: *size,
+        };
+        self.bids = bids.iter().rev().take(levels).map(level).collect();
+        self.asks = asks.iter().take(levels).map(level).collect();
+    }
+}
+
+/// set the levels of one book side, size zero removes the level
+fn set_levels(side: &mut BTreeMap<Decimal, Decimal>, levels: &[DepthLevel]) {
+    for level in levels {
+        if level.size.is_zero() {
+            side.remove(&level.price);
+        } else {
+            side.insert(level.price, level.size);
+        }
+    }
+}
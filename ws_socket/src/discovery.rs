@@ -0,0 +1,108 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    str::FromStr,
+    time::Duration,
+};
+
+use serde_json::Value;
+
+use crate::{
+    exchanges::Exchange,
+    helpers,
+    types::{ExchangeInstruments, WSResult, WebSocketConfig},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// check of the requested pairs against the instrument lists of the exchanges
+pub enum PairValidation {
+    /// connect without loading the instrument lists
+    #[default]
+    Off,
+    /// print a warning for every pair an exchange does not list
+    Warn,
+    /// also stop subscribing the pairs an exchange does not list
+    Reject,
+}
+
+impl FromStr for PairValidation {
+    type Err = String;
+
+    /// parse `off`, `warn` or `reject`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(PairValidation::Off),
+            "warn" => Ok(PairValidation::Warn),
+            "reject" => Ok(PairValidation::Reject),
+            _ => Err(format!(
+                "unknown pair validation {s}, expected off, warn or reject"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PairValidation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PairValidation::Off => write!(f, "off"),
+            PairValidation::Warn => write!(f, "warn"),
+            PairValidation::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+/// read the instrument lists cached on disk, a missing or broken file is an empty cache
+pub fn read_instruments(path: &str) -> HashMap<String, ExchangeInstruments> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// write the instrument lists to disk
+pub fn write_instruments(
+    path: &str,
+    instruments: &HashMap<String, ExchangeInstruments>,
+) -> WSResult<()> {
+    fs::write(path, serde_json::to_string(instruments)?)?;
+    Ok(())
+}
+
+/// native symbols the exchange lists, from the cache while it is fresh and from the rest api
+/// otherwise, none when the exchange has no instrument list url, sockets of the same exchange
+/// share the cached list
+pub async fn listed_symbols(
+    exchange: &dyn Exchange,
+    config: &WebSocketConfig,
+    instruments: &mut HashMap<String, ExchangeInstruments>,
+    ttl: Duration,
+) -> WSResult<Option<HashSet<String>>> {
+    let Some(url) = exchange.instruments_url(config) else {
+        return Ok(None);
+    };
+    let now = helpers::now_millis();
+    let fresh = instruments.get(exchange.name()).filter(|cached| {
+        cached.url == url && now.saturating_sub(cached.fetched_at) < ttl.as_millis() as u64
+    });
+    if let Some(cached) = fresh {
+        return Ok(Some(cached.symbols.iter().cloned().collect()));
+    }
+
+    let body: Value = helpers::http_client()?
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let symbols = exchange.parse_instruments(body)?;
+    instruments.insert(
+        exchange.name().to_string(),
+        ExchangeInstruments {
+            url,
+            fetched_at: now,
+            symbols: symbols.clone(),
+        },
+    );
+    Ok(Some(symbols.into_iter().collect()))
+}
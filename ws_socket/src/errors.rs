@@ -1,19 +1,40 @@
-use std::{io, num::ParseFloatError};
+use std::{io, time::Duration};
 use thiserror::Error;
 use tungstenite::Error as TError;
 
 #[derive(Error, Debug)]
 pub enum WSError {
-    #[error("IO error")]
+    #[error("IO error: {0}")]
     IoError(#[from] io::Error),
-    #[error("Serde Error")]
+    #[error("Serde Error: {0}")]
     SerdeError(#[from] serde_json::Error),
-    #[error("Tungsnite Error")]
-    TungsniteError(#[from] TError),
-    #[error("ParseFloatError")]
-    ParseFloatError(#[from] ParseFloatError),
+    #[error("Tungsnite Error: {0}")]
+    TungsniteError(#[from] Box<TError>),
+    #[error("Decimal Error: {0}")]
+    DecimalError(#[from] rust_decimal::Error),
     #[error("Got Unknown Response")]
     UnknownResponse,
     #[error("Socket Response Error:{0}")]
     SocketResponseError(String),
+    #[error("Subscription Rejected: {0}: {1}")]
+    SubscriptionRejected(String, String),
+    #[error("Config Error:{0}")]
+    ConfigError(String),
+    #[error("Reconnect Error:{0}")]
+    ReconnectError(String),
+    #[error("Stale Connection: no message for {0:?}")]
+    StaleConnection(Duration),
+    #[error("No Data: {0}")]
+    NoData(String),
+    #[error("Http Error: {0}")]
+    HttpError(#[from] reqwest::Error),
+    #[error("Book Out Of Sync: {0}")]
+    BookOutOfSync(String),
+}
+
+/// tungstenite error is large, keep it boxed so results stay small
+impl From<TError> for WSError {
+    fn from(error: TError) -> Self {
+        WSError::TungsniteError(Box::new(error))
+    }
 }
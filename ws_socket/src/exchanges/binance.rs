@@ -0,0 +1,370 @@
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::Exchange;
+use crate::{
+    errors::WSError,
+    helpers::{parse_levels, parse_optional, parse_quote},
+    types::{
+        BookAction, BookUpdate, Candle, Channels, Instrument, Tick, Trade, TradeSide, WSResult,
+        WebSocketConfig,
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+/// binance request parameter structure
+pub struct BinanceReqParam {
+    pub method: String,
+    pub params: Vec<String>,
+    pub id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// binance socket response structure
+pub struct BinanceResponse {
+    pub s: String,
+    pub c: String,
+    /// event time in milliseconds
+    #[serde(rename = "E", default)]
+    pub event_time: Option<u64>,
+    /// 24h base volume
+    #[serde(default)]
+    pub v: Option<String>,
+    /// 24h quote volume
+    #[serde(default)]
+    pub q: Option<String>,
+    /// last trade quantity
+    #[serde(rename = "Q", default)]
+    pub last_size: Option<String>,
+    /// best bid price
+    #[serde(default)]
+    pub b: Option<String>,
+    /// best bid quantity
+    #[serde(rename = "B", default)]
+    pub bid_size: Option<String>,
+    /// best ask price
+    #[serde(default)]
+    pub a: Option<String>,
+    /// best ask quantity
+    #[serde(rename = "A", default)]
+    pub ask_size: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// binance diff depth stream structure
+pub struct BinanceDepthUpdate {
+    pub s: String,
+    /// first update id in the event
+    #[serde(rename = "U")]
+    pub first_update_id: u64,
+    /// last update id in the event
+    #[serde(rename = "u")]
+    pub last_update_id: u64,
+    pub b: Vec<Vec<String>>,
+    pub a: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// binance trade stream structure
+pub struct BinanceTrade {
+    pub s: String,
+    /// trade id
+    pub t: u64,
+    pub p: String,
+    pub q: String,
+    /// trade time in milliseconds
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+    /// buyer is the maker, the taker sold
+    pub m: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// binance kline stream structure
+pub struct BinanceKlineEvent {
+    pub s: String,
+    pub k: BinanceKline,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// binance kline stream candle structure
+pub struct BinanceKline {
+    /// candle open time in milliseconds
+    pub t: u64,
+    /// candle close time in milliseconds, the last millisecond of the candle
+    #[serde(rename = "T")]
+    pub close_time: u64,
+    pub o: String,
+    pub h: String,
+    pub l: String,
+    pub c: String,
+    /// base volume
+    pub v: String,
+    /// the candle is closed
+    pub x: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// binance rest exchange info structure
+pub struct BinanceExchangeInfo {
+    pub symbols: Vec<BinanceSymbol>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// binance rest exchange info symbol structure
+pub struct BinanceSymbol {
+    pub symbol: String,
+    /// TRADING while the symbol is open
+    pub status: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// binance rest depth snapshot structure
+pub struct BinanceDepthSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    pub last_update_id: u64,
+    pub bids: Vec<Vec<String>>,
+    pub asks: Vec<Vec<String>>,
+}
+
+/// kline intervals of the stream in seconds
+const KLINE_INTERVALS: [(u64, &str); 15] = [
+    (1, "1s"),
+    (60, "1m"),
+    (180, "3m"),
+    (300, "5m"),
+    (900, "15m"),
+    (1800, "30m"),
+    (3600, "1h"),
+    (7200, "2h"),
+    (14400, "4h"),
+    (21600, "6h"),
+    (28800, "8h"),
+    (43200, "12h"),
+    (86400, "1d"),
+    (259200, "3d"),
+    (604800, "1w"),
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+/// binance spot ticker stream
+pub struct Binance;
+
+impl Exchange for Binance {
+    fn name(&self) -> &str {
+        "binance"
+    }
+
+    fn native_symbol(&self, instrument: &Instrument) -> String {
+        format!("{}{}", instrument.base, instrument.quote)
+    }
+
+    fn instruments_url(&self, config: &WebSocketConfig) -> Option<String> {
+        let rest_base_url = config.rest_base_url.as_ref()?;
+        Some(format!("{rest_base_url}/api/v3/exchangeInfo"))
+    }
+
+    fn parse_instruments(&self, msg: Value) -> WSResult<Vec<String>> {
+        let info: BinanceExchangeInfo = serde_json::from_value(msg)?;
+        Ok(info
+            .symbols
+            .into_iter()
+            .filter(|symbol| symbol.status == "TRADING")
+            .map(|symbol| symbol.symbol)
+            .collect())
+    }
+
+    /// binance web socket request url handle for pairs and return
+    fn connect_url(
+        &self,
+        config: &WebSocketConfig,
+        pairs: &[String],
+        channels: &Channels,
+    ) -> String {
+        let mut binance_ws_api: String = format!("{}/ws", config.ws_base_url);
+
+        for pair in pairs {
+            if let Some(symbol) = self.symbol(config, pair) {
+                let symbol = symbol.to_lowercase();
+                binance_ws_api.push_str(&format!("/{symbol}@ticker"));
+                if channels.trades {
+                    binance_ws_api.push_str(&format!("/{symbol}@trade"));
+                }
+                if channels.depth.is_some() {
+                    binance_ws_api.push_str(&format!("/{symbol}@depth@100ms"));
+                }
+                if let Some(kline) = channels
+                    .kline
+                    .and_then(|interval| self.kline_channel(interval))
+                {
+                    binance_ws_api.push_str(&format!("/{symbol}@{kline}"));
+                }
+            }
+        }
+
+        binance_ws_api
+    }
+
+    fn subscribe_message(
+        &self,
+        config: &WebSocketConfig,
+        pairs: &[String],
+        channels: &Channels,
+    ) -> WSResult<String> {
+        let mut req_param: BinanceReqParam = serde_json::from_value(config.req_param.clone())?;
+
+        for pair in pairs {
+            if let Some(symbol) = self.symbol(config, pair) {
+                req_param.params.push(format!("{symbol}@ticker"));
+                if channels.trades {
+                    req_param.params.push(format!("{symbol}@trade"));
+                }
+                if channels.depth.is_some() {
+                    req_param.params.push(format!("{symbol}@depth@100ms"));
+                }
+                if let Some(kline) = channels
+                    .kline
+                    .and_then(|interval| self.kline_channel(interval))
+                {
+                    req_param.params.push(format!("{symbol}@{kline}"));
+                }
+            }
+        }
+        Ok(serde_json::to_string(&req_param)?)
+    }
+
+    fn response_error(&self, msg: &Value) -> Option<String> {
+        if msg["result"] == "error" {
+            return Some(format!("{:?}", msg));
+        }
+        None
+    }
+
+    fn parse_message(&self, msg: Value) -> WSResult<Vec<Tick>> {
+        // subscription acknowledgements and other events are not tickers
+        let binance_response: BinanceResponse = match serde_json::from_value(msg) {
+            Ok(p) => p,
+            Err(_) => return Ok(vec![]),
+        };
+        if binance_response.s.is_empty() {
+            return Ok(vec![]);
+        }
+
+        Ok(vec![Tick {
+            pair: binance_response.s,
+            price: binance_response.c.parse::<Decimal>()?,
+            event_time: binance_response.event_time,
+            volume_24h: parse_optional(binance_response.v)?,
+            quote_volume_24h: parse_optional(binance_response.q)?,
+            last_size: parse_optional(binance_response.last_size)?,
+            quote: parse_quote(
+                binance_response.b,
+                binance_response.bid_size,
+                binance_response.a,
+                binance_response.ask_size,
+            )?,
+            ..Default::default()
+        }])
+    }
+
+    fn parse_book(&self, msg: &Value) -> WSResult<Vec<BookUpdate>> {
+        if msg["e"] != "depthUpdate" {
+            return Ok(vec![]);
+        }
+        let update: BinanceDepthUpdate = serde_json::from_value(msg.clone())?;
+        Ok(vec![BookUpdate {
+            pair: update.s,
+            action: BookAction::Update,
+            bids: parse_levels(update.b)?,
+            asks: parse_levels(update.a)?,
+            first_sequence: Some(update.first_update_id),
+            sequence: Some(update.last_update_id),
+            prev_sequence: None,
+            checksum: None,
+        }])
+    }
+
+    fn parse_trades(&self, msg: &Value) -> WSResult<Vec<Trade>> {
+        if msg["e"] != "trade" {
+            return Ok(vec![]);
+        }
+        let trade: BinanceTrade = serde_json::from_value(msg.clone())?;
+        Ok(vec![Trade {
+            exchange: self.name().to_string(),
+            pair: trade.s,
+            price: trade.p.parse()?,
+            size: trade.q.parse()?,
+            side: if trade.m {
+                TradeSide::Sell
+            } else {
+                TradeSide::Buy
+            },
+            trade_id: trade.t.to_string(),
+            ts: trade.trade_time,
+        }])
+    }
+
+    fn kline_channel(&self, interval: Duration) -> Option<String> {
+        if interval.subsec_nanos() != 0 {
+            return None;
+        }
+        KLINE_INTERVALS
+            .iter()
+            .find(|(seconds, _)| *seconds == interval.as_secs())
+            .map(|(_, name)| format!("kline_{name}"))
+    }
+
+    fn parse_candles(&self, msg: &Value) -> WSResult<Vec<Candle>> {
+        if msg["e"] != "kline" {
+            return Ok(vec![]);
+        }
+        let event: BinanceKlineEvent = serde_json::from_value(msg.clone())?;
+        Ok(vec![Candle {
+            exchange: self.name().to_string(),
+            pair: event.s,
+            start: event.k.t,
+            interval_ms: (event.k.close_time + 1).saturating_sub(event.k.t),
+            open: event.k.o.parse()?,
+            high: event.k.h.parse()?,
+            low: event.k.l.parse()?,
+            close: event.k.c.parse()?,
+            volume: event.k.v.parse()?,
+            closed: event.k.x,
+            resampled: false,
+        }])
+    }
+
+    /// the diff depth stream has no snapshot, it is fetched from the rest api
+    fn book_snapshot_url(
+        &self,
+        config: &WebSocketConfig,
+        symbol: &str,
+    ) -> WSResult<Option<String>> {
+        let Some(rest_base_url) = &config.rest_base_url else {
+            return Err(WSError::ConfigError(format!(
+                "{} needs rest_base_url for the order book snapshot",
+                config.name
+            )));
+        };
+        Ok(Some(format!(
+            "{rest_base_url}/api/v3/depth?symbol={symbol}&limit=1000"
+        )))
+    }
+
+    fn parse_book_snapshot(&self, symbol: &str, msg: Value) -> WSResult<BookUpdate> {
+        let snapshot: BinanceDepthSnapshot = serde_json::from_value(msg)?;
+        Ok(BookUpdate {
+            pair: symbol.to_string(),
+            action: BookAction::Snapshot,
+            bids: parse_levels(snapshot.bids)?,
+            asks: parse_levels(snapshot.asks)?,
+            first_sequence: None,
+            sequence: Some(snapshot.last_update_id),
+            prev_sequence: None,
+            checksum: None,
+        })
+    }
+}
@@ -0,0 +1,248 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::Exchange;
+use crate::{
+    helpers::{pair_key, parse_levels, parse_optional, parse_quote, rfc3339_millis},
+    types::{
+        BookAction, BookUpdate, Channels, DepthLevel, Instrument, Tick, Trade, TradeSide, WSResult,
+        WebSocketConfig,
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+/// coinbase request parameter structure
+pub struct CoinbaseReqParam {
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub channels: Vec<String>,
+    pub product_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// coinbase socket response structure
+pub struct CoinbaseResponse {
+    pub product_id: String,
+    pub price: String,
+    /// RFC 3339 event time
+    #[serde(default)]
+    pub time: Option<String>,
+    #[serde(default)]
+    pub volume_24h: Option<String>,
+    #[serde(default)]
+    pub last_size: Option<String>,
+    #[serde(default)]
+    pub best_bid: Option<String>,
+    #[serde(default)]
+    pub best_bid_size: Option<String>,
+    #[serde(default)]
+    pub best_ask: Option<String>,
+    #[serde(default)]
+    pub best_ask_size: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// coinbase level2 snapshot structure
+pub struct CoinbaseBookSnapshot {
+    pub product_id: String,
+    pub bids: Vec<Vec<String>>,
+    pub asks: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// coinbase level2 update structure
+pub struct CoinbaseBookUpdate {
+    pub product_id: String,
+    /// `[side, price, size]` of every changed level
+    pub changes: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// coinbase matches channel structure
+pub struct CoinbaseMatch {
+    pub product_id: String,
+    pub trade_id: u64,
+    pub price: String,
+    pub size: String,
+    /// side of the maker order
+    pub side: String,
+    /// RFC 3339 trade time
+    pub time: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// coinbase rest product structure
+pub struct CoinbaseProduct {
+    pub id: String,
+    /// online while the product is open
+    pub status: String,
+    #[serde(default)]
+    pub trading_disabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// coinbase exchange ticker channel
+pub struct Coinbase;
+
+impl Exchange for Coinbase {
+    fn name(&self) -> &str {
+        "coinbase"
+    }
+
+    fn native_symbol(&self, instrument: &Instrument) -> String {
+        format!("{}-{}", instrument.base, instrument.quote)
+    }
+
+    fn instrument(&self, symbol: &str) -> Option<Instrument> {
+        Instrument::from_dashed(symbol)
+    }
+
+    fn instruments_url(&self, config: &WebSocketConfig) -> Option<String> {
+        let rest_base_url = config.rest_base_url.as_ref()?;
+        Some(format!("{rest_base_url}/products"))
+    }
+
+    fn parse_instruments(&self, msg: Value) -> WSResult<Vec<String>> {
+        let products: Vec<CoinbaseProduct> = serde_json::from_value(msg)?;
+        Ok(products
+            .into_iter()
+            .filter(|product| product.status == "online" && !product.trading_disabled)
+            .map(|product| product.id)
+            .collect())
+    }
+
+    fn subscribe_message(
+        &self,
+        config: &WebSocketConfig,
+        pairs: &[String],
+        channels: &Channels,
+    ) -> WSResult<String> {
+        let mut req_param: CoinbaseReqParam = serde_json::from_value(config.req_param.clone())?;
+
+        // level2_batch is the public level2 channel, plain level2 needs authentication
+        if channels.depth.is_some() {
+            req_param.channels.push("level2_batch".to_string());
+        }
+        if channels.trades {
+            req_param.channels.push("matches".to_string());
+        }
+
+        // heartbeat channel keeps quiet products alive and the stale timeout fed
+        if !req_param
+            .channels
+            .iter()
+            .any(|channel| channel == "heartbeat")
+        {
+            req_param.channels.push("heartbeat".to_string());
+        }
+
+        for pair in pairs {
+            if let Some(symbol) = self.symbol(config, pair) {
+                req_param.product_ids.push(symbol);
+            }
+        }
+        Ok(serde_json::to_string(&req_param)?)
+    }
+
+    fn response_error(&self, msg: &Value) -> Option<String> {
+        if msg["type"] == "error" {
+            return Some(format!("{:?}", msg));
+        }
+        None
+    }
+
+    fn parse_message(&self, msg: Value) -> WSResult<Vec<Tick>> {
+        // subscriptions and heartbeat messages are not tickers
+        let coinbase_response: CoinbaseResponse = match serde_json::from_value(msg) {
+            Ok(p) => p,
+            Err(_) => return Ok(vec![]),
+        };
+        if coinbase_response.product_id.is_empty() {
+            return Ok(vec![]);
+        }
+
+        Ok(vec![Tick {
+            pair: pair_key(&coinbase_response.product_id),
+            price: coinbase_response.price.parse::<Decimal>()?,
+            event_time: coinbase_response.time.as_deref().and_then(rfc3339_millis),
+            volume_24h: parse_optional(coinbase_response.volume_24h)?,
+            last_size: parse_optional(coinbase_response.last_size)?,
+            quote: parse_quote(
+                coinbase_response.best_bid,
+                coinbase_response.best_bid_size,
+                coinbase_response.best_ask,
+                coinbase_response.best_ask_size,
+            )?,
+            ..Default::default()
+        }])
+    }
+
+    fn parse_trades(&self, msg: &Value) -> WSResult<Vec<Trade>> {
+        // last_match is the most recent trade sent right after subscribing
+        if msg["type"] != "match" && msg["type"] != "last_match" {
+            return Ok(vec![]);
+        }
+        let trade: CoinbaseMatch = serde_json::from_value(msg.clone())?;
+        Ok(vec![Trade {
+            exchange: self.name().to_string(),
+            pair: pair_key(&trade.product_id),
+            price: trade.price.parse()?,
+            size: trade.size.parse()?,
+            // the taker is on the other side of the maker
+            side: if trade.side == "sell" {
+                TradeSide::Buy
+            } else {
+                TradeSide::Sell
+            },
+            trade_id: trade.trade_id.to_string(),
+            ts: rfc3339_millis(&trade.time).unwrap_or_default(),
+        }])
+    }
+
+    fn parse_book(&self, msg: &Value) -> WSResult<Vec<BookUpdate>> {
+        if msg["type"] == "snapshot" {
+            let snapshot: CoinbaseBookSnapshot = serde_json::from_value(msg.clone())?;
+            return Ok(vec![BookUpdate {
+                pair: pair_key(&snapshot.product_id),
+                action: BookAction::Snapshot,
+                bids: parse_levels(snapshot.bids)?,
+                asks: parse_levels(snapshot.asks)?,
+                first_sequence: None,
+                sequence: None,
+                prev_sequence: None,
+                checksum: None,
+            }]);
+        }
+        if msg["type"] != "l2update" {
+            return Ok(vec![]);
+        }
+
+        let update: CoinbaseBookUpdate = serde_json::from_value(msg.clone())?;
+        let (mut bids, mut asks) = (vec![], vec![]);
+        for change in update.changes {
+            let [side, price, size] = change.as_slice() else {
+                continue;
+            };
+            let level = DepthLevel {
+                price: price.parse()?,
+                size: size.parse()?,
+            };
+            match side.as_str() {
+                "buy" => bids.push(level),
+                "sell" => asks.push(level),
+                _ => {}
+            }
+        }
+        Ok(vec![BookUpdate {
+            pair: pair_key(&update.product_id),
+            action: BookAction::Update,
+            bids,
+            asks,
+            first_sequence: None,
+            sequence: None,
+            prev_sequence: None,
+            checksum: None,
+        }])
+    }
+}
@@ -0,0 +1,145 @@
+use std::{fmt::Debug, time::Duration};
+
+use serde_json::Value;
+
+use crate::{
+    errors::WSError,
+    types::{
+        BookUpdate, Candle, Channels, Instrument, OrderBook, Tick, Trade, WSResult, WebSocketConfig,
+    },
+};
+
+mod binance;
+mod coinbase;
+mod okx;
+
+pub use binance::Binance;
+pub use coinbase::Coinbase;
+pub use okx::Okx;
+
+/// Exchange specific behaviour of a web socket venue.
+///
+/// Implement this trait in a single file to add a new venue, the handler,
+/// parser and cache code only talk to exchanges through it.
+pub trait Exchange: Debug + Send + Sync {
+    /// exchange name used in logs
+    fn name(&self) -> &str;
+
+    /// native symbol of the instrument, ex. BTCUSDT or BTC-USDT
+    fn native_symbol(&self, instrument: &Instrument) -> String;
+
+    /// native symbol of a "btc_usdt" pair, the config `symbols` override the exchange format
+    fn symbol(&self, config: &WebSocketConfig, pair: &str) -> Option<String> {
+        if let Some(symbol) = config.symbols.get(pair) {
+            return Some(symbol.clone());
+        }
+        Instrument::parse(pair).map(|instrument| self.native_symbol(&instrument))
+    }
+
+    /// instrument of a native symbol, none when the symbol can not be split into its coins
+    fn instrument(&self, _symbol: &str) -> Option<Instrument> {
+        None
+    }
+
+    /// rest url of the instruments the exchange lists, none without `rest_base_url`
+    fn instruments_url(&self, _config: &WebSocketConfig) -> Option<String> {
+        None
+    }
+
+    /// decode the instrument list into the native symbols open for trading
+    fn parse_instruments(&self, _msg: Value) -> WSResult<Vec<String>> {
+        Ok(vec![])
+    }
+
+    /// web socket url to connect for the pairs
+    fn connect_url(
+        &self,
+        config: &WebSocketConfig,
+        _pairs: &[String],
+        _channels: &Channels,
+    ) -> String {
+        config.ws_base_url.to_string()
+    }
+
+    /// subscribe request parameter sent after connect
+    fn subscribe_message(
+        &self,
+        config: &WebSocketConfig,
+        pairs: &[String],
+        channels: &Channels,
+    ) -> WSResult<String>;
+
+    /// return the error description when the message is an error response
+    fn response_error(&self, msg: &Value) -> Option<String>;
+
+    /// native symbol of the single subscription an error response rejects, none when the
+    /// whole subscription failed
+    fn rejected_symbol(&self, _msg: &Value) -> Option<String> {
+        None
+    }
+
+    /// decode socket message into normalized ticks
+    fn parse_message(&self, msg: Value) -> WSResult<Vec<Tick>>;
+
+    /// decode order book message, empty when the message is not an order book message
+    fn parse_book(&self, _msg: &Value) -> WSResult<Vec<BookUpdate>> {
+        Ok(vec![])
+    }
+
+    /// decode public trades message, empty when the message is not a trades message
+    fn parse_trades(&self, _msg: &Value) -> WSResult<Vec<Trade>> {
+        Ok(vec![])
+    }
+
+    /// candle channel of the interval, none when the exchange has no candles of that interval
+    fn kline_channel(&self, _interval: Duration) -> Option<String> {
+        None
+    }
+
+    /// decode candle message, empty when the message is not a candle message
+    fn parse_candles(&self, _msg: &Value) -> WSResult<Vec<Candle>> {
+        Ok(vec![])
+    }
+
+    /// rest url of the order book snapshot of a native symbol, for exchanges sending no
+    /// snapshot on subscribe
+    fn book_snapshot_url(
+        &self,
+        _config: &WebSocketConfig,
+        _symbol: &str,
+    ) -> WSResult<Option<String>> {
+        Ok(None)
+    }
+
+    /// decode the order book snapshot fetched from `book_snapshot_url`
+    fn parse_book_snapshot(&self, _symbol: &str, _msg: Value) -> WSResult<BookUpdate> {
+        Err(WSError::UnknownResponse)
+    }
+
+    /// checksum of the local book, compared with the checksum sent in book updates
+    fn book_checksum(&self, _book: &OrderBook) -> Option<i32> {
+        None
+    }
+
+    /// text message the exchange expects on idle connections
+    fn keepalive_message(&self) -> Option<String> {
+        None
+    }
+
+    /// check the text message is the reply to the keepalive message
+    fn is_keepalive_reply(&self, _message: &str) -> bool {
+        false
+    }
+}
+
+/// all exchanges supported by this crate
+pub fn supported_exchanges() -> Vec<Box<dyn Exchange>> {
+    vec![Box::new(Binance), Box::new(Coinbase), Box::new(Okx)]
+}
+
+/// find a supported exchange by its config name
+pub fn exchange_by_name(name: &str) -> Option<Box<dyn Exchange>> {
+    supported_exchanges()
+        .into_iter()
+        .find(|exchange| exchange.name() == name)
+}
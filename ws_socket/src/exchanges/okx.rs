@@ -0,0 +1,405 @@
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::Exchange;
+use crate::{
+    helpers::{pair_key, parse_levels, parse_optional, parse_quote},
+    types::{
+        BookAction, BookUpdate, Candle, Channels, Instrument, OrderBook, Tick, Trade, TradeSide,
+        WSResult, WebSocketConfig,
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex request parameter structure
+pub struct OkexReqParam {
+    pub op: String,
+    pub args: Vec<OkexReqParamArg>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex request parameter argument structure
+pub struct OkexReqParamArg {
+    pub channel: String,
+    #[serde(rename = "instId")]
+    pub inst_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex socket response child structure
+pub struct OkexResponseChild {
+    #[serde(rename = "instId")]
+    pub inst_id: String,
+    pub last: String,
+    /// event time in milliseconds
+    #[serde(default)]
+    pub ts: Option<String>,
+    /// 24h base volume
+    #[serde(rename = "vol24h", default)]
+    pub vol_24h: Option<String>,
+    /// 24h quote volume
+    #[serde(rename = "volCcy24h", default)]
+    pub vol_ccy_24h: Option<String>,
+    #[serde(rename = "lastSz", default)]
+    pub last_sz: Option<String>,
+    #[serde(rename = "bidPx", default)]
+    pub bid_px: Option<String>,
+    #[serde(rename = "bidSz", default)]
+    pub bid_sz: Option<String>,
+    #[serde(rename = "askPx", default)]
+    pub ask_px: Option<String>,
+    #[serde(rename = "askSz", default)]
+    pub ask_sz: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex socket response parent structure
+pub struct OkexResponse {
+    pub data: Vec<OkexResponseChild>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex books channel response structure
+pub struct OkexBookResponse {
+    pub arg: OkexReqParamArg,
+    /// snapshot or update
+    pub action: String,
+    pub data: Vec<OkexBookData>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex books channel data structure
+pub struct OkexBookData {
+    /// `[price, size, deprecated, order count]` levels
+    pub asks: Vec<Vec<String>>,
+    pub bids: Vec<Vec<String>>,
+    /// crc32 of the top 25 levels after the update
+    #[serde(default)]
+    pub checksum: Option<i32>,
+    /// -1 on snapshots
+    #[serde(rename = "prevSeqId", default)]
+    pub prev_seq_id: Option<i64>,
+    #[serde(rename = "seqId", default)]
+    pub seq_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex trades channel data structure
+pub struct OkexTrade {
+    #[serde(rename = "instId")]
+    pub inst_id: String,
+    #[serde(rename = "tradeId")]
+    pub trade_id: String,
+    pub px: String,
+    pub sz: String,
+    /// side of the taker
+    pub side: String,
+    /// trade time in milliseconds
+    pub ts: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex trades channel response structure
+pub struct OkexTradesResponse {
+    pub data: Vec<OkexTrade>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex candle channel response structure
+pub struct OkexCandleResponse {
+    pub arg: OkexReqParamArg,
+    /// `[ts, o, h, l, c, vol, volCcy, volCcyQuote, confirm]` of every candle
+    pub data: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex rest instruments response structure
+pub struct OkexInstrumentsResponse {
+    pub data: Vec<OkexInstrument>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+/// okex rest instrument structure
+pub struct OkexInstrument {
+    #[serde(rename = "instId")]
+    pub inst_id: String,
+    /// live while the instrument is open
+    pub state: String,
+}
+
+/// candle channels of the business socket in seconds
+const CANDLE_INTERVALS: [(u64, &str); 16] = [
+    (1, "1s"),
+    (60, "1m"),
+    (180, "3m"),
+    (300, "5m"),
+    (900, "15m"),
+    (1800, "30m"),
+    (3600, "1H"),
+    (7200, "2H"),
+    (14400, "4H"),
+    (21600, "6H"),
+    (43200, "12H"),
+    (86400, "1D"),
+    (172800, "2D"),
+    (259200, "3D"),
+    (432000, "5D"),
+    (604800, "1W"),
+];
+
+/// levels of each side okx includes in the checksum
+const CHECKSUM_LEVELS: usize = 25;
+
+#[derive(Debug, Clone, Copy, Default)]
+/// okx v5 public tickers channel
+pub struct Okx;
+
+impl Exchange for Okx {
+    fn name(&self) -> &str {
+        "okx"
+    }
+
+    fn native_symbol(&self, instrument: &Instrument) -> String {
+        format!("{}-{}", instrument.base, instrument.quote)
+    }
+
+    fn instrument(&self, symbol: &str) -> Option<Instrument> {
+        Instrument::from_dashed(symbol)
+    }
+
+    fn instruments_url(&self, config: &WebSocketConfig) -> Option<String> {
+        let rest_base_url = config.rest_base_url.as_ref()?;
+        Some(format!(
+            "{rest_base_url}/api/v5/public/instruments?instType=SPOT"
+        ))
+    }
+
+    fn parse_instruments(&self, msg: Value) -> WSResult<Vec<String>> {
+        let response: OkexInstrumentsResponse = serde_json::from_value(msg)?;
+        Ok(response
+            .data
+            .into_iter()
+            .filter(|instrument| instrument.state == "live")
+            .map(|instrument| instrument.inst_id)
+            .collect())
+    }
+
+    fn subscribe_message(
+        &self,
+        config: &WebSocketConfig,
+        pairs: &[String],
+        channels: &Channels,
+    ) -> WSResult<String> {
+        let mut req_param: OkexReqParam = serde_json::from_value(config.req_param.clone())?;
+
+        let kline = channels
+            .kline
+            .and_then(|interval| self.kline_channel(interval));
+        for pair in pairs {
+            if let Some(symbol) = self.symbol(config, pair) {
+                if let Some(kline) = &kline {
+                    req_param.args.push(OkexReqParamArg {
+                        channel: kline.clone(),
+                        inst_id: symbol.clone(),
+                    });
+                }
+                // the candle socket serves no market data channels
+                if channels.candles_only {
+                    continue;
+                }
+                req_param.args.push(OkexReqParamArg {
+                    channel: "tickers".to_string(),
+                    inst_id: symbol.clone(),
+                });
+                if channels.trades {
+                    req_param.args.push(OkexReqParamArg {
+                        channel: "trades".to_string(),
+                        inst_id: symbol.clone(),
+                    });
+                }
+                if channels.depth.is_some() {
+                    req_param.args.push(OkexReqParamArg {
+                        channel: "books".to_string(),
+                        inst_id: symbol.clone(),
+                    });
+                }
+            }
+        }
+        Ok(serde_json::to_string(&req_param)?)
+    }
+
+    fn response_error(&self, msg: &Value) -> Option<String> {
+        if msg["event"] == "error" {
+            return Some(format!("{:?}", msg));
+        }
+        None
+    }
+
+    /// okx rejects every subscription argument on its own, ex. `instId:BTC-XYZ doesn't exist`
+    fn rejected_symbol(&self, msg: &Value) -> Option<String> {
+        if let Some(inst_id) = msg["arg"]["instId"].as_str() {
+            return Some(inst_id.to_string());
+        }
+        let (_, rest) = msg["msg"].as_str()?.split_once("instId:")?;
+        let inst_id: String = rest
+            .chars()
+            .take_while(|c| !c.is_whitespace() && *c != ',')
+            .collect();
+        (!inst_id.is_empty()).then_some(inst_id)
+    }
+
+    /// okx closes connections without data for 30 seconds unless pinged
+    fn keepalive_message(&self) -> Option<String> {
+        Some("ping".to_string())
+    }
+
+    fn is_keepalive_reply(&self, message: &str) -> bool {
+        message == "pong"
+    }
+
+    fn parse_message(&self, msg: Value) -> WSResult<Vec<Tick>> {
+        // subscribe events carry no data
+        let okex_response: OkexResponse = match serde_json::from_value(msg) {
+            Ok(p) => p,
+            Err(_) => return Ok(vec![]),
+        };
+
+        let mut ticks = vec![];
+        for child in okex_response.data {
+            ticks.push(Tick {
+                pair: pair_key(&child.inst_id),
+                price: child.last.parse::<Decimal>()?,
+                event_time: child.ts.and_then(|ts| ts.parse::<u64>().ok()),
+                volume_24h: parse_optional(child.vol_24h)?,
+                quote_volume_24h: parse_optional(child.vol_ccy_24h)?,
+                last_size: parse_optional(child.last_sz)?,
+                quote: parse_quote(child.bid_px, child.bid_sz, child.ask_px, child.ask_sz)?,
+                ..Default::default()
+            });
+        }
+        Ok(ticks)
+    }
+
+    fn parse_trades(&self, msg: &Value) -> WSResult<Vec<Trade>> {
+        // subscribe events carry the channel too but no data
+        if msg["arg"]["channel"] != "trades" || msg["data"].is_null() {
+            return Ok(vec![]);
+        }
+        let response: OkexTradesResponse = serde_json::from_value(msg.clone())?;
+
+        let mut trades = vec![];
+        for trade in response.data {
+            trades.push(Trade {
+                exchange: self.name().to_string(),
+                pair: pair_key(&trade.inst_id),
+                price: trade.px.parse()?,
+                size: trade.sz.parse()?,
+                side: if trade.side == "sell" {
+                    TradeSide::Sell
+                } else {
+                    TradeSide::Buy
+                },
+                trade_id: trade.trade_id,
+                ts: trade.ts.parse().unwrap_or_default(),
+            });
+        }
+        Ok(trades)
+    }
+
+    /// candle channels are only served on the business socket, see `candle_ws_url`
+    fn kline_channel(&self, interval: Duration) -> Option<String> {
+        if interval.subsec_nanos() != 0 {
+            return None;
+        }
+        CANDLE_INTERVALS
+            .iter()
+            .find(|(seconds, _)| *seconds == interval.as_secs())
+            .map(|(_, name)| format!("candle{name}"))
+    }
+
+    fn parse_candles(&self, msg: &Value) -> WSResult<Vec<Candle>> {
+        let Some(channel) = msg["arg"]["channel"].as_str() else {
+            return Ok(vec![]);
+        };
+        let Some((seconds, _)) = CANDLE_INTERVALS
+            .iter()
+            .find(|(_, name)| channel.strip_prefix("candle") == Some(name))
+        else {
+            return Ok(vec![]);
+        };
+        if msg["data"].is_null() {
+            return Ok(vec![]);
+        }
+        let response: OkexCandleResponse = serde_json::from_value(msg.clone())?;
+
+        let mut candles = vec![];
+        for candle in response.data {
+            let [ts, open, high, low, close, volume, .., confirm] = candle.as_slice() else {
+                continue;
+            };
+            candles.push(Candle {
+                exchange: self.name().to_string(),
+                pair: pair_key(&response.arg.inst_id),
+                start: ts.parse().unwrap_or_default(),
+                interval_ms: seconds * 1000,
+                open: open.parse()?,
+                high: high.parse()?,
+                low: low.parse()?,
+                close: close.parse()?,
+                volume: volume.parse()?,
+                closed: confirm == "1",
+                resampled: false,
+            });
+        }
+        Ok(candles)
+    }
+
+    fn parse_book(&self, msg: &Value) -> WSResult<Vec<BookUpdate>> {
+        if msg["arg"]["channel"] != "books" || msg["action"].is_null() {
+            return Ok(vec![]);
+        }
+        let response: OkexBookResponse = serde_json::from_value(msg.clone())?;
+        let action = match response.action.as_str() {
+            "snapshot" => BookAction::Snapshot,
+            _ => BookAction::Update,
+        };
+
+        let mut updates = vec![];
+        for data in response.data {
+            updates.push(BookUpdate {
+                pair: pair_key(&response.arg.inst_id),
+                action,
+                bids: parse_levels(data.bids)?,
+                asks: parse_levels(data.asks)?,
+                first_sequence: None,
+                sequence: data.seq_id.and_then(|seq| u64::try_from(seq).ok()),
+                prev_sequence: match action {
+                    BookAction::Snapshot => None,
+                    BookAction::Update => data.prev_seq_id.and_then(|seq| u64::try_from(seq).ok()),
+                },
+                checksum: data.checksum,
+            });
+        }
+        Ok(updates)
+    }
+
+    /// crc32 over `bid:size:ask:size` of the top 25 levels, the longer side continues alone
+    fn book_checksum(&self, book: &OrderBook) -> Option<i32> {
+        let bids: Vec<_> = book.best_bids().take(CHECKSUM_LEVELS).collect();
+        let asks: Vec<_> = book.best_asks().take(CHECKSUM_LEVELS).collect();
+
+        let mut fields = vec![];
+        for index in 0..bids.len().max(asks.len()) {
+            if let Some(bid) = bids.get(index) {
+                fields.push(format!("{}:{}", bid.price, bid.size));
+            }
+            if let Some(ask) = asks.get(index) {
+                fields.push(format!("{}:{}", ask.price, ask.size));
+            }
+        }
+        Some(crc32fast::hash(fields.join(":").as_bytes()) as i32)
+    }
+}
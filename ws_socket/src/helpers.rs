@@ -1,152 +1,291 @@
+use flate2::read::{DeflateDecoder, GzDecoder};
+use rust_decimal::Decimal;
 use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::types::{
-    BinanceReqParam, CoinbaseReqParam, OkexReqParam, OkexReqParamArg, PairsCache, PricesPairs,
-    ResponseEnum, SocketType, WSResult, WebSocketConfig,
+use crate::{
+    errors::WSError,
+    exchanges::{self, Exchange},
+    types::{
+        Candle, Compression, DepthLevel, PairsCache, PricesPairs, Quote, ReconnectConfig, Tick,
+        WSResult, WebSocketConfig,
+    },
 };
-use serde_json::Value;
-
-/// binance web socket request url handle for pairs and return
-pub fn binance_req_url(ws_base_url: &str, pairs: &Vec<String>) -> String {
-    let mut binance_ws_api: String = format!("{}/ws", ws_base_url);
-
-    for pair in pairs {
-        let coin: Vec<&str> = pair.split('_').collect();
-        if coin.len() == 2 {
-            let query: String = format!(
-                "/{}{}@ticker",
-                coin[0].to_lowercase(),
-                coin[1].to_lowercase()
-            );
-            binance_ws_api.push_str(&query)
+
+/// how long a rest request may take in total
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// how long connecting to a rest api may take
+const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// read web socket configs file and key the configs by exchange name
+pub fn read_ws_details(path: &str) -> WSResult<HashMap<String, WebSocketConfig>> {
+    let ws_details_file: File = fs::File::open(path)?;
+    let ws_details: Vec<WebSocketConfig> = serde_json::from_reader(&ws_details_file)?;
+
+    let mut ws_details_map: HashMap<String, WebSocketConfig> = HashMap::new();
+    for config in ws_details {
+        if ws_details_map.contains_key(&config.name) {
+            return Err(WSError::ConfigError(format!(
+                "exchange {} is configured more than once",
+                config.name
+            )));
         }
+        ws_details_map.insert(config.name.to_string(), config);
     }
+    Ok(ws_details_map)
+}
 
-    binance_ws_api
-}
-
-/// create request parameters for Binance, Coinbase and Okex
-pub fn create_req_params(
-    socket_type: SocketType,
-    data: &Value,
-    pairs: &Vec<String>,
-) -> WSResult<String> {
-    let mut params = vec![];
-    for pair in pairs {
-        let coin: Vec<&str> = pair.split('_').collect();
-        if coin.len() == 2 {
-            let param = match socket_type {
-                SocketType::Binance => {
-                    format!(
-                        "{}{}@ticker",
-                        coin[0].to_uppercase(),
-                        coin[1].to_uppercase()
-                    )
-                }
-                SocketType::Okex => {
-                    format!("{}-{}", coin[0].to_uppercase(), coin[1].to_uppercase())
-                }
-                SocketType::Coinbase => {
-                    format!("{}-{}", coin[0].to_uppercase(), coin[1].to_uppercase())
-                }
-            };
-            params.push(param);
-        }
+/// check configs against the supported exchanges and return the enabled ones sorted by name
+pub fn check_ws_details(
+    ws_details: &HashMap<String, WebSocketConfig>,
+) -> WSResult<Vec<(WebSocketConfig, Box<dyn Exchange>)>> {
+    let mut unknown: Vec<&str> = ws_details
+        .keys()
+        .filter(|name| exchanges::exchange_by_name(name).is_none())
+        .map(|name| name.as_str())
+        .collect();
+    if !unknown.is_empty() {
+        unknown.sort();
+        return Err(WSError::ConfigError(format!(
+            "unknown exchanges: {}",
+            unknown.join(", ")
+        )));
     }
-    get_request_param_string(socket_type, data, params)
-}
-
-/// get request parameter in string
-pub fn get_request_param_string(
-    socket_type: SocketType,
-    data: &Value,
-    params: Vec<String>,
-) -> WSResult<String> {
-    match socket_type {
-        SocketType::Binance => {
-            let mut req_param: BinanceReqParam = serde_json::from_value(data.clone())?;
-
-            params.iter().for_each(|param| {
-                req_param.params.push(param.clone());
-            });
-            Ok(serde_json::to_string(&req_param)?)
+
+    for exchange in exchanges::supported_exchanges() {
+        if !ws_details.contains_key(exchange.name()) {
+            eprintln!("Exchange {} is missing in config", exchange.name());
         }
-        SocketType::Okex => {
-            let mut req_param: OkexReqParam =
-                serde_json::from_value(data.clone()).expect("Can't parse okex_req_param");
-            params.iter().for_each(|param| {
-                req_param.args.push(OkexReqParamArg {
-                    channel: "tickers".to_string(),
-                    inst_id: param.clone(),
-                });
-            });
-            Ok(serde_json::to_string(&req_param)?)
+    }
+
+    let mut enabled = vec![];
+    for config in ws_details.values() {
+        if !config.enabled {
+            println!("Exchange {} is disabled", config.name);
+            continue;
         }
-        SocketType::Coinbase => {
-            let mut req_param: CoinbaseReqParam =
-                serde_json::from_value(data.clone()).expect("Can't parse coinbase_req_param");
-            params.iter().for_each(|param| {
-                req_param.product_ids.push(param.clone());
-            });
-            Ok(serde_json::to_string(&req_param)?)
+        if let Some(exchange) = exchanges::exchange_by_name(&config.name) {
+            enabled.push((config.clone(), exchange));
         }
     }
+    if enabled.is_empty() {
+        return Err(WSError::ConfigError("no exchange is enabled".to_string()));
+    }
+    enabled.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+
+    Ok(enabled)
+}
+
+/// split "btc_usdt" pair into uppercase base and quote coins
+pub fn pair_coins(pair: &str) -> Option<(String, String)> {
+    let coin: Vec<&str> = pair.split('_').collect();
+    if coin.len() == 2 {
+        Some((coin[0].to_uppercase(), coin[1].to_uppercase()))
+    } else {
+        None
+    }
+}
+
+/// parse optional decimal string field
+pub fn parse_optional(value: Option<String>) -> WSResult<Option<Decimal>> {
+    // exchanges send empty strings for fields without a value
+    Ok(value
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse::<Decimal>())
+        .transpose()?)
+}
+
+/// parse order book levels sent as `[price, size, ..]` strings
+pub fn parse_levels(levels: Vec<Vec<String>>) -> WSResult<Vec<DepthLevel>> {
+    levels
+        .into_iter()
+        .map(|level| match (level.first(), level.get(1)) {
+            (Some(price), Some(size)) => Ok(DepthLevel {
+                price: price.parse()?,
+                size: size.parse()?,
+            }),
+            _ => Err(WSError::UnknownResponse),
+        })
+        .collect()
+}
+
+/// parse top of book fields, none unless both bid and ask are sent
+pub fn parse_quote(
+    bid: Option<String>,
+    bid_size: Option<String>,
+    ask: Option<String>,
+    ask_size: Option<String>,
+) -> WSResult<Option<Quote>> {
+    match (parse_optional(bid)?, parse_optional(ask)?) {
+        (Some(bid), Some(ask)) => Ok(Some(Quote {
+            bid,
+            ask,
+            bid_size: parse_optional(bid_size)?,
+            ask_size: parse_optional(ask_size)?,
+        })),
+        _ => Ok(None),
+    }
 }
 
-/// remove "-" from the string and return the pairkey
+/// remove separators from a symbol and return the uppercase pair key, ex. BTC-USDT -> BTCUSDT
 pub fn pair_key(string: &str) -> String {
-    let c_pair: Vec<&str> = string.split('-').collect();
-    format!("{}{}", c_pair[0].to_uppercase(), c_pair[1].to_uppercase())
+    string
+        .chars()
+        .filter(|c| !matches!(c, '-' | '_' | '/'))
+        .collect::<String>()
+        .to_uppercase()
 }
 
-/// common handler fror socket response
+/// common handler for decoded exchange ticks
 pub fn handle_response(
     pairs_cache: &mut HashMap<String, PairsCache>,
-    ws_details: &[WebSocketConfig],
-    response: ResponseEnum,
-) -> WSResult<()> {
-    match response {
-        ResponseEnum::Binance(binance_response) => {
-            if !binance_response.s.is_empty() {
-                let price = binance_response.c.parse::<f64>()?;
-                update_price_cache(
-                    pairs_cache,
-                    binance_response.s.to_string(),
-                    ws_details[0].name.to_string(),
-                    price,
-                );
-            }
-        }
-        ResponseEnum::Okex(okex_response) => {
-            if !okex_response.data.is_empty() {
-                let price = okex_response.data[0].last.parse::<f64>()?;
+    name: &str,
+    ticks: Vec<Tick>,
+) {
+    for tick in ticks {
+        update_price_cache(pairs_cache, name.to_string(), tick);
+    }
+}
 
-                // get pair cache and push okex response, name and price
-                let key = pair_key(&okex_response.data[0].inst_id);
-                update_price_cache(pairs_cache, key, ws_details[2].name.to_string(), price);
-            }
+/// keep the latest update of every candle, a newer candle of the exchange closes the older ones
+pub fn update_candles(candles: &mut Vec<Candle>, candle: Candle) {
+    for older in candles
+        .iter_mut()
+        .filter(|older| older.exchange == candle.exchange && older.start < candle.start)
+    {
+        older.closed = true;
+    }
+    match candles
+        .iter_mut()
+        .find(|same| same.exchange == candle.exchange && same.start == candle.start)
+    {
+        Some(same) => {
+            let closed = same.closed;
+            *same = candle;
+            same.closed |= closed;
         }
-        ResponseEnum::Coinbase(coinbase_response) => {
-            if !coinbase_response.product_id.is_empty() {
-                let price = coinbase_response.price.parse::<f64>()?;
-                // get pair cache and push coinbase response, name and price
-                let key = pair_key(&coinbase_response.product_id);
-                update_price_cache(pairs_cache, key, ws_details[1].name.to_string(), price);
+        None => candles.push(candle),
+    }
+}
+
+/// update price cache in hashmap
+fn update_price_cache(pairs_cache: &mut HashMap<String, PairsCache>, name: String, tick: Tick) {
+    if let Some(pair) = pairs_cache.get_mut(&tick.pair) {
+        pair.prices.push(PricesPairs {
+            name,
+            price: tick.price,
+            event_time: tick.event_time,
+            received_at: tick.received_at,
+            received_mono: tick.received_mono,
+            volume_24h: tick.volume_24h,
+            quote_volume_24h: tick.quote_volume_24h,
+            last_size: tick.last_size,
+            quote: tick.quote,
+            rejected: None,
+            instrument: tick.instrument,
+            rate: tick.rate,
+            legs: tick.legs,
+        });
+    }
+}
+
+/// current unix time in milliseconds
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// process wide monotonic clock zero with its unix time in milliseconds
+fn clock_epoch() -> &'static (Instant, u64) {
+    static EPOCH: OnceLock<(Instant, u64)> = OnceLock::new();
+    EPOCH.get_or_init(|| (Instant::now(), now_millis()))
+}
+
+/// monotonic microseconds since the clock epoch
+pub fn monotonic_micros() -> u64 {
+    clock_epoch().0.elapsed().as_micros() as u64
+}
+
+/// unix time in milliseconds of monotonic zero
+pub fn monotonic_epoch() -> u64 {
+    clock_epoch().1
+}
+
+/// parse RFC 3339 time into unix time in milliseconds
+pub fn rfc3339_millis(time: &str) -> Option<u64> {
+    let time = humantime::parse_rfc3339(time).ok()?;
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_millis() as u64)
+}
+
+/// delay before a reconnect attempt, exponential with equal jitter
+pub fn reconnect_backoff(config: &ReconnectConfig, attempt: u32) -> Duration {
+    let backoff = config
+        .initial_backoff
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(config.max_backoff);
+    let half = backoff / 2;
+    half + half.mul_f64(fastrand::f64())
+}
+
+/// resolve on Ctrl-C or SIGTERM, never when signals are not handled
+pub async fn shutdown_signal(enabled: bool) {
+    if !enabled {
+        return std::future::pending().await;
+    }
+
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
             }
+            Err(e) => eprintln!("SIGTERM handler failed: {}", e),
         }
     }
 
-    Ok(())
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        eprintln!("Ctrl-C handler failed: {}", e);
+        std::future::pending::<()>().await;
+    }
 }
 
-/// update price cache in hashmap
-fn update_price_cache(
-    pairs_cache: &mut HashMap<String, PairsCache>,
-    key: String,
-    name: String,
-    price: f64,
-) {
-    if let Some(pair) = pairs_cache.get_mut(&key) {
-        pair.prices.push(PricesPairs { name, price });
+/// client for the rest api of the exchanges, a hanging endpoint fails instead of blocking
+/// the collection
+pub fn http_client() -> WSResult<reqwest::Client> {
+    // coinbase rejects requests without a user agent
+    Ok(reqwest::Client::builder()
+        .user_agent(concat!("ws_socket/", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(HTTP_CONNECT_TIMEOUT)
+        .timeout(HTTP_TIMEOUT)
+        .build()?)
+}
+
+/// decode binary frame into text according to the exchange compression
+pub fn decompress(compression: Compression, data: &[u8]) -> WSResult<String> {
+    let mut text = String::new();
+    match compression {
+        Compression::None => {
+            text = String::from_utf8(data.to_vec()).map_err(|_| WSError::UnknownResponse)?;
+        }
+        Compression::Gzip => {
+            GzDecoder::new(data).read_to_string(&mut text)?;
+        }
+        Compression::Deflate => {
+            DeflateDecoder::new(data).read_to_string(&mut text)?;
+        }
     }
+    Ok(text)
 }
@@ -0,0 +1,224 @@
+use std::{collections::HashSet, fmt, time::Duration};
+
+use crate::{
+    exchanges::Exchange,
+    helpers,
+    types::{Instrument, InstrumentKind, SymbolMap, Synthetic, Update, WebSocketConfig},
+};
+
+impl Instrument {
+    /// parse a "btc_usdt" pair into a spot instrument
+    pub fn parse(pair: &str) -> Option<Instrument> {
+        let (base, quote) = helpers::pair_coins(pair)?;
+        if base.is_empty() || quote.is_empty() {
+            return None;
+        }
+        Some(Instrument {
+            base,
+            quote,
+            kind: InstrumentKind::Spot,
+        })
+    }
+
+    /// parse a dash separated native symbol, ex. BTC-USDT
+    pub fn from_dashed(symbol: &str) -> Option<Instrument> {
+        let (base, quote) = symbol.split_once('-')?;
+        Instrument::parse(&format!("{base}_{quote}"))
+    }
+
+    /// instrument with the quote replaced by the first preferred quote equivalent to it
+    pub fn listed_as(&self, groups: &[Vec<String>], preferred: &[String]) -> Instrument {
+        let equivalent = |quote: &str| {
+            groups.iter().any(|group| {
+                group
+                    .iter()
+                    .any(|coin| coin.eq_ignore_ascii_case(&self.quote))
+                    && group.iter().any(|coin| coin.eq_ignore_ascii_case(quote))
+            })
+        };
+        match preferred.iter().find(|quote| equivalent(quote)) {
+            Some(quote) => Instrument {
+                quote: quote.to_uppercase(),
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// cache key of the instrument, ex. BTCUSDT
+    pub fn key(&self) -> String {
+        format!("{}{}", self.base, self.quote)
+    }
+}
+
+impl fmt::Display for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}_{}",
+            self.base.to_lowercase(),
+            self.quote.to_lowercase()
+        )
+    }
+}
+
+impl Synthetic {
+    /// legs of the pair against the bridge coin, ex. sol_eth via usdt is sol_usdt / eth_usdt
+    pub fn new(pair: &Instrument, bridge: &str, max_staleness: Duration) -> Synthetic {
+        let leg = |coin: &str| Instrument {
+            base: coin.to_string(),
+            quote: bridge.to_uppercase(),
+            kind: pair.kind,
+        };
+        Synthetic {
+            base_leg: leg(&pair.base),
+            quote_leg: leg(&pair.quote),
+            max_staleness_ms: max_staleness.as_millis() as u64,
+        }
+    }
+}
+
+impl SymbolMap {
+    /// native symbols of the pairs on the exchange, listed with the preferred equivalent quote
+    /// of the exchange, config overrides win over both
+    pub fn new(
+        exchange: &dyn Exchange,
+        config: &WebSocketConfig,
+        pairs: &[String],
+        groups: &[Vec<String>],
+    ) -> SymbolMap {
+        let mut symbols = SymbolMap::default();
+        for pair in pairs {
+            let Some(instrument) = Instrument::parse(pair) else {
+                continue;
+            };
+            let subscribed = if config.symbols.contains_key(pair) {
+                pair.to_string()
+            } else {
+                instrument
+                    .listed_as(groups, &config.preferred_quotes)
+                    .to_string()
+            };
+            symbols.insert(exchange, config, instrument, subscribed);
+        }
+        symbols
+    }
+
+    /// also subscribe the rate of every pair quote in its listed quote, ex. usdt_usd
+    pub fn with_rates(mut self, exchange: &dyn Exchange, config: &WebSocketConfig) -> SymbolMap {
+        let mut rates = vec![];
+        for (key, listed) in &self.listed {
+            for instrument in self.instruments.get(key).into_iter().flatten() {
+                let rate = format!("{}_{}", instrument.quote, listed.quote).to_lowercase();
+                if instrument.quote != listed.quote && !rates.contains(&rate) {
+                    rates.push(rate);
+                }
+            }
+        }
+        rates.sort();
+        for rate in rates {
+            if let Some(instrument) = Instrument::parse(&rate) {
+                self.insert(exchange, config, instrument, rate);
+            }
+        }
+        self
+    }
+
+    fn insert(
+        &mut self,
+        exchange: &dyn Exchange,
+        config: &WebSocketConfig,
+        instrument: Instrument,
+        subscribed: String,
+    ) {
+        let Some(native) = exchange.symbol(config, &subscribed) else {
+            return;
+        };
+        let listed = exchange
+            .instrument(&native)
+            .or_else(|| Instrument::parse(&subscribed))
+            .unwrap_or_else(|| instrument.clone());
+        let key = helpers::pair_key(&native);
+        if !self.pairs.contains(&subscribed) {
+            self.pairs.push(subscribed);
+        }
+        self.natives.insert(instrument.to_string(), native);
+        let instruments = self.instruments.entry(key.clone()).or_default();
+        if !instruments.contains(&instrument) {
+            instruments.push(instrument);
+        }
+        self.listed.insert(key, listed);
+    }
+
+    /// pairs handed to the exchange to subscribe
+    pub fn pairs(&self) -> &[String] {
+        &self.pairs
+    }
+
+    /// native symbols of every subscribed pair, once each
+    pub fn natives(&self) -> Vec<String> {
+        let mut natives: Vec<String> = self.natives.values().cloned().collect();
+        natives.sort();
+        natives.dedup();
+        natives
+    }
+
+    /// pairs whose native symbol is not in the symbols the exchange lists
+    pub fn unlisted(&self, listed: &HashSet<String>) -> Vec<String> {
+        let listed: HashSet<String> = listed
+            .iter()
+            .map(|symbol| helpers::pair_key(symbol))
+            .collect();
+        let mut unlisted: Vec<String> = self
+            .natives
+            .iter()
+            .filter(|(_, native)| !listed.contains(&helpers::pair_key(native)))
+            .map(|(pair, _)| pair.clone())
+            .collect();
+        unlisted.sort();
+        unlisted
+    }
+
+    /// native symbol of the pair, ex. btc_usdt -> BTC-USDT
+    pub fn native(&self, pair: &str) -> Option<&str> {
+        let instrument = Instrument::parse(pair)?;
+        self.natives
+            .get(&instrument.to_string())
+            .map(|native| native.as_str())
+    }
+
+    /// instruments requested from a native symbol, with or without separators
+    pub fn instruments(&self, symbol: &str) -> &[Instrument] {
+        self.instruments
+            .get(&helpers::pair_key(symbol))
+            .map(|instruments| instruments.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// requested and listed instrument of a pair key, ex. BTCUSDT
+    pub fn listing(&self, key: &str) -> Option<(&Instrument, &Instrument)> {
+        self.instruments.iter().find_map(|(native, instruments)| {
+            let instrument = instruments
+                .iter()
+                .find(|instrument| instrument.key() == key)?;
+            Some((instrument, self.listed.get(native)?))
+        })
+    }
+
+    /// copies of a decoded update renamed from its native symbol to the key of every pair
+    /// requested from it, the update unchanged when none is
+    pub fn normalize(&self, mut update: Update) -> Vec<Update> {
+        let instruments = self.instruments(update.pair_mut());
+        if instruments.is_empty() {
+            return vec![update];
+        }
+        instruments
+            .iter()
+            .map(|instrument| {
+                let mut update = update.clone();
+                *update.pair_mut() = instrument.key();
+                update
+            })
+            .collect()
+    }
+}
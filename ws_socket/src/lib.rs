@@ -1,45 +1,161 @@
 #[cfg(test)]
 mod test;
 
-use futures_util::StreamExt;
-use std::collections::HashMap;
-use std::fs::{self, File};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::num::NonZeroU64;
 use std::time::Duration;
-use tokio::time;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+    time,
+};
 
-mod types;
+pub use crate::aggregate::{Aggregation, OutlierFilter};
+pub use crate::discovery::PairValidation;
+use crate::errors::WSError;
+use crate::exchanges::exchange_by_name;
 use crate::types::*;
+pub use crate::types::{
+    Bar, Bars, Bbo, BookDepth, Candle, Channels, CollectorConfig, Compression, Depth, DepthLevel,
+    ExchangeState, ExchangeStats, ExchangeStatus, ExchangesCache, HeartbeatConfig, Instrument,
+    InstrumentKind, Legs, PairState, PairsCache, PricesPairs, Quote, ReconnectConfig, Spread,
+    Synthetic, Tick, Trade, TradeSide, WSResult, WebSocketConfig,
+};
+pub use rust_decimal::Decimal;
+pub mod aggregate;
+pub mod book;
+pub mod discovery;
 pub mod errors;
+pub mod exchanges;
 pub mod helpers;
+pub mod instrument;
 pub mod parser;
+pub mod types;
 
-/// start execution
-pub async fn start() -> WSResult<()> {
-    let args: Args = Args::parse();
-    let mode: String = args.mode;
+/// how long a socket may take to send its close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
 
-    if mode == "cache" {
-        // get pairs from the argument
-        let pairs: String = args.pairs;
+/// status key suffix of the socket serving the candles of an exchange, ex. `okx:candles`
+const CANDLES_SUFFIX: &str = ":candles";
 
-        if !pairs.is_empty() {
-            if check_pairs(&pairs) {
-                let pairs: Vec<_> = pairs.split(',').collect();
-                let pairs_string_vec: Vec<String> = pairs.iter().map(|i| i.to_string()).collect();
+/// collect pairs from the configured exchanges and return them with their aggregate
+pub async fn run(config: &CollectorConfig) -> WSResult<ExchangesCache> {
+    if config.pairs.is_empty() && config.synthetic_pairs.is_empty() {
+        return Err(WSError::ConfigError("pairs is required".to_string()));
+    }
+    for pair in config.pairs.iter().chain(&config.synthetic_pairs) {
+        if Instrument::parse(pair).is_none() {
+            return Err(WSError::ConfigError(format!(
+                "pair {pair} is not valid format"
+            )));
+        }
+    }
+    for pair in config
+        .synthetic_pairs
+        .iter()
+        .filter_map(|pair| Instrument::parse(pair))
+    {
+        if config.bridge.eq_ignore_ascii_case(&pair.base)
+            || config.bridge.eq_ignore_ascii_case(&pair.quote)
+        {
+            return Err(WSError::ConfigError(format!(
+                "synthetic pair {pair} can not be derived via its own coin {}",
+                config.bridge
+            )));
+        }
+    }
+    if config
+        .bar_interval
+        .is_some_and(|interval| interval.as_millis() == 0)
+    {
+        return Err(WSError::ConfigError(
+            "bar interval should be at least 1ms".to_string(),
+        ));
+    }
+    if config
+        .channels
+        .kline
+        .is_some_and(|interval| interval.as_secs() == 0 || interval.subsec_nanos() != 0)
+    {
+        return Err(WSError::ConfigError(
+            "kline interval should be whole seconds".to_string(),
+        ));
+    }
 
-                handle_cache_mode(pairs_string_vec).await?;
-            }
-        } else {
-            println!("Pairs is required");
+    let mut cache = handle_cache_mode(config).await?;
+    // candle sockets send no prices, they are never missing a pair
+    let mut exchanges: Vec<String> = cache
+        .exchanges
+        .keys()
+        .filter(|name| !name.ends_with(CANDLES_SUFFIX))
+        .cloned()
+        .collect();
+    exchanges.sort();
+    let subscribed_at: HashMap<String, u64> = cache
+        .exchanges
+        .iter()
+        .filter_map(|(name, status)| Some((name.clone(), status.subscribed_at?)))
+        .collect();
+    cache.pairs = aggregate_pairs_cache(
+        cache.pairs,
+        &exchanges,
+        &subscribed_at,
+        config.aggregation,
+        config.outlier_filter,
+        config.bar_interval,
+        config.channels.kline,
+    );
+
+    let mut keys: Vec<&String> = cache.pairs.keys().collect();
+    keys.sort();
+    for key in keys {
+        let pair_cache = &cache.pairs[key];
+        for name in &pair_cache.missing {
+            eprintln!("Warning: exchange {name} produced no data for pair {key}");
+        }
+        if pair_cache.state == PairState::NoData {
+            eprintln!("Warning: pair {key} has no data");
         }
-    } else if mode == "read" {
-        handle_read_mode()?;
+    }
+    Ok(cache)
+}
+
+/// collect pairs and write the cache to the configured output file
+pub async fn run_cache(config: &CollectorConfig) -> WSResult<()> {
+    let cache = run(config).await?;
+    write_pairs_cache(&config.output_path, &cache).await?;
+    if cache.interrupted {
+        println!("Cache interrupted");
     } else {
-        println!("Invalid mode");
+        println!("Cache complete");
+    }
+
+    if config.require_data {
+        let mut empty: Vec<&str> = cache
+            .pairs
+            .iter()
+            .filter(|(_, pair_cache)| pair_cache.state == PairState::NoData)
+            .map(|(key, _)| key.as_str())
+            .collect();
+        if !empty.is_empty() {
+            empty.sort();
+            return Err(WSError::NoData(format!(
+                "pairs without data: {}",
+                empty.join(", ")
+            )));
+        }
     }
     Ok(())
 }
 
+/// read cache file written by `run_cache`
+pub fn read_cache(path: &str) -> WSResult<ExchangesCache> {
+    let content = fs::File::open(path)?;
+    let cache: ExchangesCache = serde_json::from_reader(&content)?;
+    Ok(cache)
+}
+
 /// check pair is valid format
 pub fn check_pairs(pairs: &str) -> bool {
     let pairs_split: Vec<&str> = pairs.split(',').collect();
@@ -57,131 +173,351 @@ pub fn check_pairs(pairs: &str) -> bool {
     count == pairs_split.len()
 }
 
-/// handle cache mode argument and collect data from multiple exchange
-async fn handle_cache_mode(pairs: Vec<String>) -> WSResult<()> {
+/// handle cache mode and collect data from multiple exchange
+async fn handle_cache_mode(config: &CollectorConfig) -> WSResult<ExchangesCache> {
     // read json file of web socket urls
-    let ws_details_file: File = fs::File::open("ws_details.json")?;
-
-    let ws_details: Vec<WebSocketConfig> = serde_json::from_reader(&ws_details_file)?;
-
-    let mut binance_handler = WSHandler::new(&ws_details[0], SocketType::Binance, pairs.clone());
-    let mut coinbase_handler = WSHandler::new(&ws_details[1], SocketType::Coinbase, pairs.clone());
-    let mut okex_handler = WSHandler::new(&ws_details[2], SocketType::Okex, pairs.clone());
-
-    // connect binance socket and subscribe
-    binance_handler.connect().await?;
-    binance_handler.subscribe().await?;
+    let ws_details = helpers::read_ws_details(&config.config_path)?;
 
-    // connect coinbase socket and subscribe
-    coinbase_handler.connect().await?;
-    coinbase_handler.subscribe().await?;
-
-    // connect okex socket and subscribe
-    okex_handler.connect().await?;
-    okex_handler.subscribe().await?;
+    let mut enabled = helpers::check_ws_details(&ws_details)?;
+    if !config.exchanges.is_empty() {
+        for name in &config.exchanges {
+            if !enabled.iter().any(|(ws_config, _)| &ws_config.name == name) {
+                return Err(WSError::ConfigError(format!(
+                    "exchange {name} is not configured or not enabled"
+                )));
+            }
+        }
+        enabled.retain(|(ws_config, _)| config.exchanges.contains(&ws_config.name));
+    }
 
-    let binance_s = binance_handler
-        .socket_stream
-        .as_mut()
-        .expect("There is some issue in binance socket stream");
-    let (_, mut binance_read) = binance_s.split();
+    let synthetics: HashMap<String, Synthetic> = config
+        .synthetic_pairs
+        .iter()
+        .filter_map(|pair| Instrument::parse(pair))
+        .map(|pair| {
+            let synthetic = Synthetic::new(&pair, &config.bridge, config.max_leg_staleness);
+            (pair.key(), synthetic)
+        })
+        .collect();
 
-    let coinbase_s = coinbase_handler
-        .socket_stream
-        .as_mut()
-        .expect("There is some issue in coinbase socket stream");
-    let (_, mut coinbase_read) = coinbase_s.split();
+    let mut handlers: Vec<WSHandler> = vec![];
+    for (ws_config, exchange) in enabled {
+        let mut channels = config.channels.clone();
+        // exchanges serving candles on another socket get a second handler for them
+        if let (Some(kline), Some(candle_ws_url)) = (channels.kline, &ws_config.candle_ws_url) {
+            // the candle socket reports its own status next to the ticker socket
+            let candle_config = WebSocketConfig {
+                name: format!("{}{CANDLES_SUFFIX}", ws_config.name),
+                ws_base_url: candle_ws_url.clone(),
+                ..ws_config.clone()
+            };
+            if let Some(candle_exchange) = exchange_by_name(&ws_config.name) {
+                handlers.push(
+                    WSHandler::new(&candle_config, candle_exchange, config.pairs.clone())
+                        .with_reconnect(config.reconnect.clone())
+                        .with_heartbeat(config.heartbeat.clone())
+                        .with_quotes(&config.quote_groups, config.convert_quotes)
+                        .with_channels(Channels {
+                            kline: Some(kline),
+                            candles_only: true,
+                            ..Default::default()
+                        }),
+                );
+            }
+            channels.kline = None;
+        }
+        handlers.push(
+            WSHandler::new(&ws_config, exchange, config.pairs.clone())
+                .with_reconnect(config.reconnect.clone())
+                .with_heartbeat(config.heartbeat.clone())
+                .with_quotes(&config.quote_groups, config.convert_quotes)
+                .with_synthetics(synthetics.clone())
+                .with_channels(channels),
+        );
+    }
 
-    let okex_s = okex_handler
-        .socket_stream
-        .as_mut()
-        .expect("There is some issue in okex socket stream");
-    let (_, mut okex_read) = okex_s.split();
+    let mut cache = ExchangesCache {
+        monotonic_epoch: helpers::monotonic_epoch(),
+        ..Default::default()
+    };
+    for handler in &handlers {
+        cache
+            .exchanges
+            .insert(handler.name().to_string(), ExchangeStatus::default());
+    }
+    let handlers = validate_pairs(config, handlers, &mut cache).await;
 
-    let mut pairs_cache: HashMap<String, PairsCache> = HashMap::new();
+    // every socket connects and reads in its own task, a failing exchange does not stop the others
+    let (events_sender, mut events) = mpsc::unbounded_channel();
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    let tasks: Vec<JoinHandle<()>> = handlers
+        .into_iter()
+        .map(|handler| tokio::spawn(handler.read_loop(events_sender.clone(), shutdown.clone())))
+        .collect();
+    drop(events_sender);
 
-    insert_pairs(pairs, &mut pairs_cache);
+    insert_pairs(config.pairs.clone(), &mut cache.pairs);
+    insert_pairs(config.synthetic_pairs.clone(), &mut cache.pairs);
+    for (key, synthetic) in synthetics {
+        if let Some(pair_cache) = cache.pairs.get_mut(&key) {
+            pair_cache.synthetic = Some(synthetic);
+        }
+    }
 
-    let mut interval = time::interval(Duration::from_secs(10));
-    let mut interval_flag = false;
+    let deadline = time::sleep(config.duration);
+    tokio::pin!(deadline);
+    let signal = helpers::shutdown_signal(config.handle_signals);
+    tokio::pin!(signal);
     loop {
         tokio::select! {
-            msg = binance_read.next() => {
-                if let Some(msg) = msg {
-                    let response = parser::message_parser(SocketType::Binance,msg)?;
-                    helpers::handle_response(&mut pairs_cache,&ws_details, response)?;
-                }
-            },
-            msg = coinbase_read.next() => {
-                if let Some(msg) = msg {
-                    let response = parser::message_parser(SocketType::Coinbase,msg)?;
-                    helpers::handle_response(&mut pairs_cache,&ws_details, response)?;
-                }
+            event = events.recv() => match event {
+                Some(event) => handle_socket_event(&mut cache, event),
+                // every exchange stopped
+                None => break,
             },
-            msg = okex_read.next() => {
-                if let Some(msg) = msg {
+            _ = &mut deadline => {
+                break;
+            }
+            _ = &mut signal => {
+                println!("Signal received, closing sockets");
+                cache.interrupted = true;
+                break;
+            }
+        }
+    }
+
+    // close every socket, keep what arrived while closing
+    let _ = shutdown_sender.send(true);
+    for mut task in tasks {
+        if time::timeout(CLOSE_TIMEOUT, &mut task).await.is_err() {
+            task.abort();
+        }
+    }
+    while let Ok(event) = events.try_recv() {
+        handle_socket_event(&mut cache, event);
+    }
+    Ok(cache)
+}
+
+/// check the pairs of every handler against the instruments its exchange lists, a failing
+/// instrument list keeps the handler as it is
+async fn validate_pairs(
+    config: &CollectorConfig,
+    handlers: Vec<WSHandler>,
+    cache: &mut ExchangesCache,
+) -> Vec<WSHandler> {
+    if config.pair_validation == PairValidation::Off {
+        return handlers;
+    }
+    let cached = discovery::read_instruments(&config.instruments_path);
+    let mut instruments = cached.clone();
+    let mut warned = HashSet::new();
+    let mut validated = vec![];
+    for handler in handlers {
+        let listed = discovery::listed_symbols(
+            handler.exchange(),
+            handler.config(),
+            &mut instruments,
+            config.instruments_ttl,
+        )
+        .await;
+        let listed = match listed {
+            Ok(Some(listed)) => listed,
+            Ok(None) => {
+                validated.push(handler);
+                continue;
+            }
+            Err(error) => {
+                eprintln!(
+                    "Exchange {} instruments not loaded: {error}",
+                    handler.name()
+                );
+                validated.push(handler);
+                continue;
+            }
+        };
+        let unlisted = handler.unlisted_pairs(&listed);
+        for pair in &unlisted {
+            let exchange = handler.exchange().name();
+            if warned.insert((exchange.to_string(), pair.clone())) {
+                eprintln!("Exchange {exchange} does not list pair {pair}");
+            }
+        }
+        if config.pair_validation != PairValidation::Reject || unlisted.is_empty() {
+            validated.push(handler);
+            continue;
+        }
+        let handler = handler.without_pairs(&unlisted);
+        if handler.pairs().is_empty() {
+            if let Some(status) = cache.exchanges.get_mut(handler.name()) {
+                status.fail("lists none of the pairs".to_string());
+            }
+            continue;
+        }
+        validated.push(handler);
+    }
+    if instruments != cached {
+        if let Err(error) = discovery::write_instruments(&config.instruments_path, &instruments) {
+            eprintln!("Instruments cache not written: {error}");
+        }
+    }
+    validated
+}
 
-                    let response = parser::message_parser(SocketType::Okex,msg)?;
-                    helpers::handle_response(&mut pairs_cache,&ws_details, response)?;
+/// apply socket event to the cache and the exchange status
+fn handle_socket_event(cache: &mut ExchangesCache, event: SocketEvent) {
+    match event {
+        SocketEvent::Subscribed { name, at } => {
+            if let Some(status) = cache.exchanges.get_mut(&name) {
+                status.subscribed_at.get_or_insert(at);
+            }
+        }
+        SocketEvent::Ticks { name, ticks } => {
+            helpers::handle_response(&mut cache.pairs, &name, ticks);
+        }
+        SocketEvent::Trades { trades, .. } => {
+            for trade in trades {
+                if let Some(pair_cache) = cache.pairs.get_mut(&trade.pair) {
+                    pair_cache.trades.push(trade);
                 }
             }
-            _ = interval.tick() => {
-                if interval_flag {
-                    write_pairs_cache(pairs_cache).await?;
-                    println!("Cache complete");
-                    break;
+        }
+        SocketEvent::Candles { candles, .. } => {
+            for candle in candles {
+                if let Some(pair_cache) = cache.pairs.get_mut(&candle.pair) {
+                    helpers::update_candles(&mut pair_cache.candles, candle);
                 }
-                interval_flag =true;
+            }
+        }
+        SocketEvent::Depth { name, pair, depth } => {
+            if let Some(pair_cache) = cache.pairs.get_mut(&pair) {
+                pair_cache
+                    .depth
+                    .get_or_insert_with(Depth::default)
+                    .by_exchange
+                    .insert(name, depth);
+            }
+        }
+        SocketEvent::Reconnect(reconnect) => {
+            if let Some(status) = cache.exchanges.get_mut(&reconnect.name) {
+                status.degrade(format!("reconnected: {}", reconnect.reason));
+            }
+            cache.reconnects.push(reconnect);
+        }
+        SocketEvent::Degraded { name, reason } => {
+            eprintln!("Exchange {name} degraded: {reason}");
+            if let Some(status) = cache.exchanges.get_mut(&name) {
+                status.degrade(reason);
+            }
+        }
+        SocketEvent::Error { name, error } => {
+            eprintln!("Exchange {name} failed: {error}");
+            if let Some(status) = cache.exchanges.get_mut(&name) {
+                status.fail(error.to_string());
             }
         }
     }
-    Ok(())
 }
 
 /// insert initial key and pairs in hashmap
 fn insert_pairs(pairs: Vec<String>, pairs_cache: &mut HashMap<String, PairsCache>) {
     for pair in pairs {
-        let coin: Vec<&str> = pair.split('_').collect();
+        let Some(instrument) = Instrument::parse(&pair) else {
+            continue;
+        };
 
-        pairs_cache.insert(
-            format!("{}{}", coin[0].to_uppercase(), coin[1].to_uppercase()),
-            PairsCache {
-                prices: vec![],
-                aggregate: 0.0,
-            },
-        );
+        pairs_cache.insert(instrument.key(), PairsCache::default());
     }
 }
 
-/// aggregate prices pair wise and write caches in file
-async fn write_pairs_cache(pairs: HashMap<String, PairsCache>) -> WSResult<()> {
+/// aggregate prices pair wise with the selected aggregation, outliers are left out
+fn aggregate_pairs_cache(
+    pairs: HashMap<String, PairsCache>,
+    exchanges: &[String],
+    subscribed_at: &HashMap<String, u64>,
+    aggregation: Aggregation,
+    outlier_filter: OutlierFilter,
+    bar_interval: Option<Duration>,
+    kline: Option<Duration>,
+) -> HashMap<String, PairsCache> {
+    // the last price of every pair stood until the collection ended
+    let end = helpers::monotonic_micros();
+    // resampled candles are closed when their interval lies between the subscription of
+    // the exchange and the end of the collection
+    let now = helpers::now_millis();
+    // intervals below a millisecond have no bars, run rejects them before collecting
+    let interval_ms = |interval: Duration| NonZeroU64::new(interval.as_millis() as u64);
+    let bar_ms = bar_interval.and_then(interval_ms);
+    let kline_ms = kline.and_then(interval_ms);
+
     let mut pairs_save = pairs.clone();
     for pair in pairs {
         let (key, mut pari_cache) = pair;
 
-        let mut amount = 0.0;
-        for price in &pari_cache.prices {
-            amount += &price.price;
+        outlier_filter.reject(&mut pari_cache.prices);
+        let accepted: Vec<PricesPairs> = pari_cache
+            .prices
+            .iter()
+            .filter(|price| price.rejected.is_none())
+            .cloned()
+            .collect();
+
+        pari_cache.aggregate = aggregation.aggregate(&accepted, end);
+        // mid and microprice need quotes, prices without them still count as data
+        pari_cache.state = if accepted.is_empty() {
+            PairState::NoData
+        } else {
+            PairState::Ok
+        };
+        pari_cache.missing = exchanges
+            .iter()
+            .filter(|name| !pari_cache.prices.iter().any(|price| &price.name == *name))
+            .cloned()
+            .collect();
+        pari_cache.aggregation = aggregation;
+        pari_cache.outlier_filter = outlier_filter;
+        pari_cache.vwap = aggregate::vwap(&accepted, &pari_cache.trades);
+        pari_cache.by_exchange = aggregate::exchange_stats(&accepted);
+        pari_cache.spread = aggregate::spread(&pari_cache.by_exchange);
+        pari_cache.bbo = aggregate::bbo(&accepted);
+        if let Some(depth) = pari_cache.depth.as_mut() {
+            depth.consolidate();
+        }
+        pari_cache.bars = bar_ms.map(|interval_ms| aggregate::bars(&accepted, interval_ms));
+
+        // in progress candles are dropped, only their final update is kept
+        pari_cache.candles.retain(|candle| candle.closed);
+        if let (Some(interval), Some(interval_ms)) = (kline, kline_ms) {
+            for name in exchanges {
+                let native = exchange_by_name(name)
+                    .is_some_and(|exchange| exchange.kline_channel(interval).is_some());
+                if let (false, Some(&start)) = (native, subscribed_at.get(name)) {
+                    let points: Vec<PricesPairs> = accepted
+                        .iter()
+                        .filter(|price| &price.name == name)
+                        .cloned()
+                        .collect();
+                    pari_cache.candles.extend(aggregate::resampled_candles(
+                        &points,
+                        &key,
+                        interval_ms,
+                        (start, now),
+                    ));
+                }
+            }
+            pari_cache
+                .candles
+                .sort_by(|a, b| (&a.exchange, a.start).cmp(&(&b.exchange, b.start)));
         }
-        pari_cache.aggregate = amount / pari_cache.prices.len() as f64;
         pairs_save.insert(key, pari_cache);
     }
-    let content = serde_json::to_string(&pairs_save)?;
-    fs::write("exchanges.json", content)?;
-
-    Ok(())
+    pairs_save
 }
 
-/// Handle Read mode argument and print the aggregate of pairs
-fn handle_read_mode() -> WSResult<()> {
-    let content = fs::File::open("exchanges.json")?;
-    let pairs: HashMap<String, PairsCache> = serde_json::from_reader(&content)?;
-
-    for pair in &pairs {
-        let (key, pari_cache) = pair;
-
-        println!("pair: {:?} -> aggregate: {:?}", key, pari_cache.aggregate);
-    }
+/// write pairs caches in file
+async fn write_pairs_cache(path: &str, cache: &ExchangesCache) -> WSResult<()> {
+    let content = serde_json::to_string(cache)?;
+    fs::write(path, content)?;
 
     Ok(())
 }
@@ -1,84 +1,53 @@
 use crate::{
     errors::WSError,
-    types::{BinanceResponse, CoinbaseResponse, OkexResponse, ResponseEnum, SocketType, WSResult},
+    exchanges::Exchange,
+    helpers,
+    types::{Compression, Update, WSResult},
 };
-use serde_json::Value;
 use tokio_tungstenite::tungstenite::{Error, Message};
 
 /// parse message
 pub fn message_parser(
-    socket_type: SocketType,
+    exchange: &dyn Exchange,
+    compression: Compression,
     msg: Result<Message, Error>,
-) -> WSResult<ResponseEnum> {
+) -> WSResult<Vec<Update>> {
     let message = match msg? {
-        Message::Text(s) => Ok(s),
-        _ => Err(WSError::UnknownResponse),
-    }?;
+        Message::Text(s) => s,
+        Message::Binary(data) => helpers::decompress(compression, &data)?,
+        // control frames carry no data, tungstenite answers pings itself
+        Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => return Ok(vec![]),
+        _ => return Err(WSError::UnknownResponse),
+    };
+
+    if exchange.is_keepalive_reply(&message) {
+        return Ok(vec![]);
+    }
 
     let msg: serde_json::Value = serde_json::from_str(&message)?;
 
-    match socket_type {
-        SocketType::Binance => {
-            let msg = parse_binance_response(msg)?;
-            Ok(ResponseEnum::Binance(msg))
-        }
-        SocketType::Okex => {
-            let msg = parse_okex_response(msg)?;
-            Ok(ResponseEnum::Okex(msg))
-        }
-        SocketType::Coinbase => {
-            let msg = parse_coinbase_response(msg)?;
-            Ok(ResponseEnum::Coinbase(msg))
+    if let Some(error) = exchange.response_error(&msg) {
+        if let Some(symbol) = exchange.rejected_symbol(&msg) {
+            return Err(WSError::SubscriptionRejected(symbol, error));
         }
-    }
-}
-
-/// parse binance data from socket response
-fn parse_binance_response(msg: Value) -> WSResult<BinanceResponse> {
-    if msg["result"] == "error" {
-        let error = format!("{:?}", msg);
         return Err(WSError::SocketResponseError(error));
     }
-    // Serialize binance response
-    let binance_response: BinanceResponse = match serde_json::from_value(msg) {
-        Ok(p) => p,
-        Err(_) => BinanceResponse {
-            s: "".to_string(),
-            c: "0.0".to_string(),
-        },
-    };
-    Ok(binance_response)
-}
 
-/// parse coinbase data from socket response
-fn parse_coinbase_response(msg: Value) -> WSResult<CoinbaseResponse> {
-    if msg["type"] == "error" {
-        let error = format!("{:?}", msg);
-        return Err(WSError::SocketResponseError(error));
+    let books = exchange.parse_book(&msg)?;
+    if !books.is_empty() {
+        return Ok(books.into_iter().map(Update::Book).collect());
     }
-
-    // Serialize coinbase response
-    let coinbase_response: CoinbaseResponse = match serde_json::from_value(msg) {
-        Ok(p) => p,
-        Err(_) => CoinbaseResponse {
-            product_id: "".to_string(),
-            price: "0.0".to_string(),
-        },
-    };
-    Ok(coinbase_response)
-}
-
-/// parse okex data from socket response
-fn parse_okex_response(msg: Value) -> WSResult<OkexResponse> {
-    if msg["event"] == "error" {
-        let error = format!("{:?}", msg);
-        return Err(WSError::SocketResponseError(error));
+    let trades = exchange.parse_trades(&msg)?;
+    if !trades.is_empty() {
+        return Ok(trades.into_iter().map(Update::Trade).collect());
     }
-
-    // Serialize okex response
-    let okex_response: OkexResponse = match serde_json::from_value(msg) {
-        Ok(p) => p,
-        Err(_) => OkexResponse { data: vec![] },
-    };
-    Ok(okex_response)
+    let candles = exchange.parse_candles(&msg)?;
+    if !candles.is_empty() {
+        return Ok(candles.into_iter().map(Update::Candle).collect());
+    }
+    Ok(exchange
+        .parse_message(msg)?
+        .into_iter()
+        .map(Update::Tick)
+        .collect())
 }
@@ -1,18 +1,41 @@
 use crate::{
-    check_pairs,
-    helpers::{self, create_req_params, handle_response},
+    aggregate::{
+        bars, bbo, exchange_stats, resampled_candles, spread, vwap, Aggregation, OutlierFilter,
+    },
+    aggregate_pairs_cache, check_pairs,
+    discovery::{listed_symbols, read_instruments, write_instruments, PairValidation},
+    errors::WSError,
+    exchanges::{Binance, Coinbase, Exchange, Okx},
+    handle_socket_event,
+    helpers::{check_ws_details, handle_response, pair_key, read_ws_details, reconnect_backoff},
     insert_pairs,
+    parser::message_parser,
+    run,
     types::{
-        BinanceResponse, CoinbaseResponse, OkexResponse, OkexResponseChild, PairsCache,
-        PricesPairs, ResponseEnum,
-        SocketType::{Binance, Coinbase, Okex},
-        WSResult, WebSocketConfig,
+        Bar, BookAction, BookDepth, BookUpdate, Candle, Channels, CollectorConfig, Compression,
+        Depth, DepthLevel, ExchangeState, ExchangeStatus, ExchangesCache, HeartbeatConfig,
+        Instrument, InstrumentKind, OrderBook, PairState, PairsCache, PricesPairs, Quote,
+        ReconnectConfig, SocketEvent, SymbolMap, Synthetic, Tick, Trade, TradeSide, Update,
+        WSHandler, WSResult, WebSocketConfig,
     },
 };
+use flate2::write::{DeflateEncoder, GzEncoder};
+use futures_util::{SinkExt, StreamExt};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde_json::json;
 use std::{
-    collections::HashMap,
-    fs::{self, File},
+    collections::{HashMap, HashSet},
+    io::Write,
+    num::NonZeroU64,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::{mpsc, watch},
 };
+use tokio_tungstenite::{accept_async, tungstenite::Message};
 
 #[test]
 /// check valid pairs for single and multiple
@@ -35,11 +58,11 @@ fn check_invalid_pairs_() {
 #[test]
 /// check binance url for single and multiple pairs
 fn check_binance_url() -> WSResult<()> {
-    let ws_details_file: File = fs::File::open("../ws_details.json")?;
-    let ws_details: Vec<WebSocketConfig> = serde_json::from_reader(&ws_details_file)?;
+    let ws_details = read_ws_details("../ws_details.json")?;
 
     let single_pairs = vec!["btc_usdt".to_string()];
-    let single_pair_url = helpers::binance_req_url(&ws_details[0].ws_base_url, &single_pairs);
+    let single_pair_url =
+        Binance.connect_url(&ws_details["binance"], &single_pairs, &Channels::default());
 
     assert_eq!(
         single_pair_url,
@@ -47,7 +70,11 @@ fn check_binance_url() -> WSResult<()> {
     );
 
     let multiple_pairs = vec!["btc_usdt".to_string(), "eth_usdt".to_string()];
-    let multiple_pair_url = helpers::binance_req_url(&ws_details[0].ws_base_url, &multiple_pairs);
+    let multiple_pair_url = Binance.connect_url(
+        &ws_details["binance"],
+        &multiple_pairs,
+        &Channels::default(),
+    );
 
     assert_eq!(
         multiple_pair_url,
@@ -60,11 +87,11 @@ fn check_binance_url() -> WSResult<()> {
 #[test]
 /// check binance subscription parameter with single and multiple pairs
 fn check_binance_subscribe_param() -> WSResult<()> {
-    let ws_details_file: File = fs::File::open("../ws_details.json")?;
-    let ws_details: Vec<WebSocketConfig> = serde_json::from_reader(&ws_details_file)?;
+    let ws_details = read_ws_details("../ws_details.json")?;
     let single_pair = vec!["btc_usdt".to_string()];
 
-    let sin_res_pair = create_req_params(Binance, &ws_details[0].req_param, &single_pair)?;
+    let sin_res_pair =
+        Binance.subscribe_message(&ws_details["binance"], &single_pair, &Channels::default())?;
 
     assert_eq!(
         sin_res_pair,
@@ -73,7 +100,8 @@ fn check_binance_subscribe_param() -> WSResult<()> {
 
     let multiple_pair = vec!["btc_usdt".to_string(), "eth_usdt".to_string()];
 
-    let mul_res_pair = create_req_params(Binance, &ws_details[0].req_param, &multiple_pair)?;
+    let mul_res_pair =
+        Binance.subscribe_message(&ws_details["binance"], &multiple_pair, &Channels::default())?;
 
     assert_eq!(
         mul_res_pair,
@@ -86,25 +114,29 @@ fn check_binance_subscribe_param() -> WSResult<()> {
 #[test]
 /// check coinbase subscription parameter with single and multiple pairs
 fn check_coinbase_subscribe_param() -> WSResult<()> {
-    let ws_details_file: File = fs::File::open("../ws_details.json")?;
-    let ws_details: Vec<WebSocketConfig> = serde_json::from_reader(&ws_details_file)?;
+    let ws_details = read_ws_details("../ws_details.json")?;
     let single_pair = vec!["btc_usdt".to_string()];
 
-    let sin_res_pair = create_req_params(Coinbase, &ws_details[1].req_param, &single_pair)?;
+    let sin_res_pair =
+        Coinbase.subscribe_message(&ws_details["coinbase"], &single_pair, &Channels::default())?;
 
     assert_eq!(
         sin_res_pair,
-        "{\"type\":\"subscribe\",\"channels\":[\"ticker\"],\"product_ids\":[\"BTC-USDT\"]}"
+        "{\"type\":\"subscribe\",\"channels\":[\"ticker\",\"heartbeat\"],\"product_ids\":[\"BTC-USDT\"]}"
             .to_string()
     );
 
     let multiple_pair = vec!["btc_usdt".to_string(), "eth_usdt".to_string()];
 
-    let mul_res_pair = create_req_params(Coinbase, &ws_details[1].req_param, &multiple_pair)?;
+    let mul_res_pair = Coinbase.subscribe_message(
+        &ws_details["coinbase"],
+        &multiple_pair,
+        &Channels::default(),
+    )?;
 
     assert_eq!(
         mul_res_pair,
-        "{\"type\":\"subscribe\",\"channels\":[\"ticker\"],\"product_ids\":[\"BTC-USDT\",\"ETH-USDT\"]}".to_string()
+        "{\"type\":\"subscribe\",\"channels\":[\"ticker\",\"heartbeat\"],\"product_ids\":[\"BTC-USDT\",\"ETH-USDT\"]}".to_string()
     );
     Ok(())
 }
@@ -112,11 +144,11 @@ fn check_coinbase_subscribe_param() -> WSResult<()> {
 #[test]
 /// check okex subscription parameter with single and multiple pairs
 fn check_okex_subscribe_param() -> WSResult<()> {
-    let ws_details_file: File = fs::File::open("../ws_details.json")?;
-    let ws_details: Vec<WebSocketConfig> = serde_json::from_reader(&ws_details_file)?;
+    let ws_details = read_ws_details("../ws_details.json")?;
     let single_pair = vec!["btc_usdt".to_string()];
 
-    let sin_res_pair = create_req_params(Okex, &ws_details[2].req_param, &single_pair)?;
+    let sin_res_pair =
+        Okx.subscribe_message(&ws_details["okx"], &single_pair, &Channels::default())?;
 
     assert_eq!(
         sin_res_pair,
@@ -126,7 +158,8 @@ fn check_okex_subscribe_param() -> WSResult<()> {
 
     let multiple_pair = vec!["btc_usdt".to_string(), "eth_usdt".to_string()];
 
-    let mul_res_pair = create_req_params(Okex, &ws_details[2].req_param, &multiple_pair)?;
+    let mul_res_pair =
+        Okx.subscribe_message(&ws_details["okx"], &multiple_pair, &Channels::default())?;
 
     assert_eq!(
         mul_res_pair,
@@ -138,34 +171,43 @@ fn check_okex_subscribe_param() -> WSResult<()> {
 #[test]
 /// check binance response
 fn check_binance_response() -> WSResult<()> {
-    let ws_details_file: File = fs::File::open("../ws_details.json")?;
-    let ws_details: Vec<WebSocketConfig> = serde_json::from_reader(&ws_details_file)?;
+    let ws_details = read_ws_details("../ws_details.json")?;
     let pairs = vec!["btc_usdt".to_string()];
 
     let mut pairs_cache: HashMap<String, PairsCache> = HashMap::new();
 
     insert_pairs(pairs, &mut pairs_cache);
 
-    let binance_response = BinanceResponse {
-        s: "BTCUSDT".to_string(),
-        c: "28933.33".to_string(),
-    };
+    let binance_response = json!({
+        "s": "BTCUSDT",
+        "c": "28933.33",
+        "E": 1_666_222_102_061u64,
+        "v": "1000.5",
+        "q": "28948303.66",
+        "Q": "0.25",
+    });
 
     handle_response(
         &mut pairs_cache,
-        &ws_details,
-        ResponseEnum::Binance(binance_response),
-    )?;
+        &ws_details["binance"].name,
+        Binance.parse_message(binance_response)?,
+    );
 
     let mut expect_response: HashMap<String, PairsCache> = HashMap::new();
     expect_response.insert(
         "BTCUSDT".to_string(),
         PairsCache {
-            aggregate: 0.0,
+            state: PairState::Ok,
             prices: vec![PricesPairs {
                 name: "binance".to_string(),
-                price: 28_933.33,
+                price: dec!(28933.33),
+                event_time: Some(1_666_222_102_061),
+                volume_24h: Some(dec!(1000.5)),
+                quote_volume_24h: Some(dec!(28948303.66)),
+                last_size: Some(dec!(0.25)),
+                ..Default::default()
             }],
+            ..Default::default()
         },
     );
 
@@ -177,34 +219,37 @@ fn check_binance_response() -> WSResult<()> {
 #[test]
 /// check coinbase response
 fn check_coinbase_response() -> WSResult<()> {
-    let ws_details_file: File = fs::File::open("../ws_details.json")?;
-    let ws_details: Vec<WebSocketConfig> = serde_json::from_reader(&ws_details_file)?;
+    let ws_details = read_ws_details("../ws_details.json")?;
     let pairs = vec!["btc_usdt".to_string()];
 
     let mut pairs_cache: HashMap<String, PairsCache> = HashMap::new();
 
     insert_pairs(pairs, &mut pairs_cache);
 
-    let coinbase_response = CoinbaseResponse {
-        price: "28933.33".to_string(),
-        product_id: "btc-usdt".to_string(),
-    };
+    let coinbase_response = json!({
+        "price": "28933.33",
+        "product_id": "btc-usdt",
+        "time": "2022-10-19T23:28:22.061769Z",
+    });
 
     handle_response(
         &mut pairs_cache,
-        &ws_details,
-        ResponseEnum::Coinbase(coinbase_response),
-    )?;
+        &ws_details["coinbase"].name,
+        Coinbase.parse_message(coinbase_response)?,
+    );
 
     let mut expect_response: HashMap<String, PairsCache> = HashMap::new();
     expect_response.insert(
         "BTCUSDT".to_string(),
         PairsCache {
-            aggregate: 0.0,
+            state: PairState::Ok,
             prices: vec![PricesPairs {
                 name: "coinbase".to_string(),
-                price: 28_933.33,
+                price: dec!(28933.33),
+                event_time: Some(1_666_222_102_061),
+                ..Default::default()
             }],
+            ..Default::default()
         },
     );
 
@@ -216,36 +261,39 @@ fn check_coinbase_response() -> WSResult<()> {
 #[test]
 /// check okex response
 fn check_okex_response() -> WSResult<()> {
-    let ws_details_file: File = fs::File::open("../ws_details.json")?;
-    let ws_details: Vec<WebSocketConfig> = serde_json::from_reader(&ws_details_file)?;
+    let ws_details = read_ws_details("../ws_details.json")?;
     let pairs = vec!["btc_usdt".to_string()];
 
     let mut pairs_cache: HashMap<String, PairsCache> = HashMap::new();
 
     insert_pairs(pairs, &mut pairs_cache);
 
-    let okex_response = OkexResponse {
-        data: vec![OkexResponseChild {
-            inst_id: "btc-usdt".to_string(),
-            last: "28933.33".to_string(),
+    let okex_response = json!({
+        "data": [{
+            "instId": "btc-usdt",
+            "last": "28933.33",
+            "ts": "1666222102061",
         }],
-    };
+    });
 
     handle_response(
         &mut pairs_cache,
-        &ws_details,
-        ResponseEnum::Okex(okex_response),
-    )?;
+        &ws_details["okx"].name,
+        Okx.parse_message(okex_response)?,
+    );
 
     let mut expect_response: HashMap<String, PairsCache> = HashMap::new();
     expect_response.insert(
         "BTCUSDT".to_string(),
         PairsCache {
-            aggregate: 0.0,
+            state: PairState::Ok,
             prices: vec![PricesPairs {
                 name: "okx".to_string(),
-                price: 28_933.33,
+                price: dec!(28933.33),
+                event_time: Some(1_666_222_102_061),
+                ..Default::default()
             }],
+            ..Default::default()
         },
     );
 
@@ -253,3 +301,1451 @@ fn check_okex_response() -> WSResult<()> {
 
     Ok(())
 }
+
+#[test]
+/// check error responses are detected for every exchange
+fn check_error_response() {
+    let exchanges: Vec<(Box<dyn Exchange>, &str)> = vec![
+        (Box::new(Binance), r#"{"result":"error","id":1}"#),
+        (Box::new(Coinbase), r#"{"type":"error","message":"Failed"}"#),
+        (Box::new(Okx), r#"{"event":"error","code":"60012"}"#),
+    ];
+
+    for (exchange, msg) in exchanges {
+        let response = message_parser(
+            exchange.as_ref(),
+            Compression::None,
+            Ok(Message::Text(msg.to_string())),
+        );
+        assert!(matches!(response, Err(WSError::SocketResponseError(_))));
+    }
+}
+
+#[test]
+/// check configs are keyed by name and disabled exchanges are skipped
+fn check_ws_details_enabled() -> WSResult<()> {
+    let mut ws_details = read_ws_details("../ws_details.json")?;
+
+    let enabled = check_ws_details(&ws_details)?;
+    let names: Vec<&str> = enabled
+        .iter()
+        .map(|(config, _)| config.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["binance", "coinbase", "okx"]);
+
+    if let Some(config) = ws_details.get_mut("coinbase") {
+        config.enabled = false;
+    }
+    ws_details.remove("okx");
+    let enabled = check_ws_details(&ws_details)?;
+    let names: Vec<&str> = enabled
+        .iter()
+        .map(|(config, _)| config.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["binance"]);
+
+    Ok(())
+}
+
+#[test]
+/// check unknown exchange in config is reported
+fn check_ws_details_unknown() -> WSResult<()> {
+    let mut ws_details = read_ws_details("../ws_details.json")?;
+    let mut unknown: WebSocketConfig = ws_details["binance"].clone();
+    unknown.name = "kraken".to_string();
+    ws_details.insert(unknown.name.to_string(), unknown);
+
+    let response = check_ws_details(&ws_details);
+    assert!(matches!(response, Err(WSError::ConfigError(e)) if e.contains("kraken")));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check run rejects missing and invalid pairs before connecting
+async fn check_run_invalid_pairs() {
+    let config = CollectorConfig::default();
+    assert!(matches!(run(&config).await, Err(WSError::ConfigError(_))));
+
+    let config = CollectorConfig {
+        pairs: vec!["btcusdt".to_string()],
+        ..Default::default()
+    };
+    assert!(matches!(run(&config).await, Err(WSError::ConfigError(_))));
+}
+
+#[test]
+/// check reconnect backoff grows exponentially within jitter bounds
+fn check_reconnect_backoff() {
+    let config = ReconnectConfig {
+        max_attempts: 5,
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_millis(1000),
+    };
+
+    for (attempt, full) in [
+        (0, 100),
+        (1, 200),
+        (2, 400),
+        (3, 800),
+        (4, 1000),
+        (10, 1000),
+    ] {
+        let backoff = reconnect_backoff(&config, attempt);
+        assert!(backoff >= Duration::from_millis(full / 2));
+        assert!(backoff <= Duration::from_millis(full));
+    }
+}
+
+/// local web socket stub, every connection sends its messages after the subscribe
+/// request, all connections but the last are closed afterwards. Text messages the
+/// stub receives are returned through the channel
+async fn stub_server(
+    connections: Vec<Vec<&'static str>>,
+) -> WSResult<(String, mpsc::UnboundedReceiver<String>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("ws://{}", listener.local_addr()?);
+    let (received_sender, received) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let last = connections.len() - 1;
+        for (connection, messages) in connections.into_iter().enumerate() {
+            let (stream, _) = listener.accept().await.expect("accept failed");
+            let mut socket = accept_async(stream).await.expect("handshake failed");
+            if let Some(Ok(Message::Text(subscribe))) = socket.next().await {
+                let _ = received_sender.send(subscribe);
+            }
+            for message in messages {
+                let message = Message::Text(message.to_string());
+                socket.send(message).await.expect("send failed");
+            }
+            if connection == last {
+                while let Some(Ok(message)) = socket.next().await {
+                    if let Message::Text(text) = message {
+                        let _ = received_sender.send(text);
+                    }
+                }
+                return;
+            }
+            socket.close(None).await.expect("close failed");
+        }
+    });
+    Ok((url, received))
+}
+
+/// spawn binance handler against the stub and return its events with the shutdown sender
+async fn stub_events(
+    url: String,
+) -> WSResult<(mpsc::UnboundedReceiver<SocketEvent>, watch::Sender<bool>)> {
+    let mut config = read_ws_details("../ws_details.json")?["binance"].clone();
+    config.ws_base_url = url;
+
+    let reconnect = ReconnectConfig {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+    };
+    let handler = WSHandler::new(&config, Box::new(Binance), vec!["btc_usdt".to_string()])
+        .with_reconnect(reconnect);
+
+    let (sender, events) = mpsc::unbounded_channel();
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    tokio::spawn(handler.read_loop(sender, shutdown));
+    Ok((events, shutdown_sender))
+}
+
+#[tokio::test]
+/// check handler reconnects and subscribes again when the server closes the socket
+async fn check_reconnect() -> WSResult<()> {
+    let ticker = r#"{"s":"BTCUSDT","c":"28933.33"}"#;
+    let (url, _) = stub_server(vec![vec![ticker], vec![ticker]]).await?;
+    let (mut events, _shutdown) = stub_events(url).await?;
+
+    match events.recv().await {
+        Some(SocketEvent::Subscribed { name, at }) => {
+            assert_eq!(name, "binance");
+            assert!(at > 0);
+        }
+        event => panic!("expected subscribed, got {:?}", event),
+    }
+    assert!(matches!(
+        events.recv().await,
+        Some(SocketEvent::Ticks { .. })
+    ));
+    match events.recv().await {
+        Some(SocketEvent::Reconnect(reconnect)) => {
+            assert_eq!(reconnect.name, "binance");
+            assert_eq!(reconnect.attempts, 1);
+            assert!(reconnect.reconnected_at.is_some());
+        }
+        event => panic!("expected reconnect, got {:?}", event),
+    }
+    assert!(matches!(
+        events.recv().await,
+        Some(SocketEvent::Ticks { .. })
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check a bad message degrades the exchange and an error response fails it
+async fn check_exchange_isolation() -> WSResult<()> {
+    let (url, _) = stub_server(vec![vec![
+        r#"{"s":"BTCUSDT","c":"not a price"}"#,
+        r#"{"s":"BTCUSDT","c":"28933.33"}"#,
+        r#"{"result":"error","id":1}"#,
+    ]])
+    .await?;
+    let (mut events, _shutdown) = stub_events(url).await?;
+
+    let mut cache = ExchangesCache::default();
+    insert_pairs(vec!["btc_usdt".to_string()], &mut cache.pairs);
+    cache
+        .exchanges
+        .insert("binance".to_string(), ExchangeStatus::default());
+
+    while let Some(event) = events.recv().await {
+        handle_socket_event(&mut cache, event);
+    }
+
+    assert_eq!(cache.pairs["BTCUSDT"].prices.len(), 1);
+    assert_eq!(cache.exchanges["binance"].status, ExchangeState::Failed);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check a rejected okx pair only degrades the exchange and the other pairs keep streaming
+async fn check_rejected_subscription() -> WSResult<()> {
+    let rejected = r#"{"event":"error","code":"60018","msg":"Wrong URL or channel:tickers,instId:FOO-USDT doesn't exist.","connId":"a4d3ae55"}"#;
+    let (url, _) = stub_server(vec![vec![
+        rejected,
+        r#"{"arg":{"channel":"tickers","instId":"BTC-USDT"},"data":[{"instId":"BTC-USDT","last":"30000"}]}"#,
+    ]])
+    .await?;
+    let mut config = read_ws_details("../ws_details.json")?["okx"].clone();
+    config.ws_base_url = url;
+    let pairs = vec!["btc_usdt".to_string(), "foo_usdt".to_string()];
+    let handler = WSHandler::new(&config, Box::new(Okx), pairs.clone());
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let (_shutdown, shutdown) = watch::channel(false);
+    tokio::spawn(handler.read_loop(sender, shutdown));
+
+    let mut cache = ExchangesCache::default();
+    insert_pairs(pairs, &mut cache.pairs);
+    cache
+        .exchanges
+        .insert("okx".to_string(), ExchangeStatus::default());
+    for _ in 0..3 {
+        let event = events.recv().await.expect("socket event");
+        handle_socket_event(&mut cache, event);
+    }
+    let status = &cache.exchanges["okx"];
+    assert_eq!(status.status, ExchangeState::Degraded);
+    assert!(status
+        .reason
+        .as_deref()
+        .is_some_and(|r| r.contains("FOO-USDT")));
+    assert_eq!(cache.pairs["BTCUSDT"].prices.len(), 1);
+
+    // nothing is left once the only pair is rejected
+    let (url, _) = stub_server(vec![vec![rejected]]).await?;
+    config.ws_base_url = url;
+    let handler = WSHandler::new(&config, Box::new(Okx), vec!["foo_usdt".to_string()]);
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let (_shutdown, shutdown) = watch::channel(false);
+    tokio::spawn(handler.read_loop(sender, shutdown));
+    assert!(matches!(
+        events.recv().await,
+        Some(SocketEvent::Subscribed { .. })
+    ));
+    assert!(matches!(
+        events.recv().await,
+        Some(SocketEvent::Error {
+            error: WSError::SubscriptionRejected(..),
+            ..
+        })
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check shutdown closes the socket and ends the handler
+async fn check_shutdown_close() -> WSResult<()> {
+    let (url, _) = stub_server(vec![vec![r#"{"s":"BTCUSDT","c":"28933.33"}"#]]).await?;
+    let (mut events, shutdown) = stub_events(url).await?;
+
+    assert!(matches!(
+        events.recv().await,
+        Some(SocketEvent::Subscribed { .. })
+    ));
+    assert!(matches!(
+        events.recv().await,
+        Some(SocketEvent::Ticks { .. })
+    ));
+    let _ = shutdown.send(true);
+    assert!(events.recv().await.is_none());
+
+    Ok(())
+}
+
+#[test]
+/// check control frames and keepalive replies decode into no ticks
+fn check_control_frames() -> WSResult<()> {
+    let ping = message_parser(&Binance, Compression::None, Ok(Message::Ping(vec![1])))?;
+    assert!(ping.is_empty());
+
+    let pong = message_parser(
+        &Okx,
+        Compression::None,
+        Ok(Message::Text("pong".to_string())),
+    )?;
+    assert!(pong.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check idle socket raises the stale connection error
+async fn check_stale_connection() -> WSResult<()> {
+    let (url, _) = stub_server(vec![vec![r#"{"s":"BTCUSDT","c":"28933.33"}"#]]).await?;
+    let mut config = read_ws_details("../ws_details.json")?["binance"].clone();
+    config.ws_base_url = url;
+
+    let heartbeat = HeartbeatConfig {
+        keepalive_interval: Duration::from_millis(10),
+        stale_timeout: Duration::from_millis(50),
+    };
+    let mut handler = WSHandler::new(&config, Box::new(Binance), vec!["btc_usdt".to_string()])
+        .with_heartbeat(heartbeat);
+    handler.connect_and_subscribe().await?;
+
+    let updates = handler.next_updates_alive().await;
+    assert!(matches!(
+        updates.as_ref().map(|updates| updates.as_deref()),
+        Some(Ok([Update::Tick(tick)])) if tick.received_at > 0
+    ));
+    let stale = handler.next_updates_alive().await;
+    assert!(matches!(stale, Some(Err(WSError::StaleConnection(_)))));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check okx keepalive ping is sent on idle socket
+async fn check_okx_keepalive() -> WSResult<()> {
+    let (url, mut received) = stub_server(vec![vec![]]).await?;
+    let mut config = read_ws_details("../ws_details.json")?["okx"].clone();
+    config.ws_base_url = url;
+
+    let heartbeat = HeartbeatConfig {
+        keepalive_interval: Duration::from_millis(10),
+        stale_timeout: Duration::from_secs(5),
+    };
+    let mut handler = WSHandler::new(&config, Box::new(Okx), vec!["btc_usdt".to_string()])
+        .with_heartbeat(heartbeat);
+    handler.connect_and_subscribe().await?;
+    tokio::spawn(async move { handler.next_updates_alive().await });
+
+    let subscribe = received.recv().await;
+    assert!(matches!(subscribe, Some(s) if s.contains("subscribe")));
+    assert_eq!(received.recv().await, Some("ping".to_string()));
+
+    Ok(())
+}
+
+#[test]
+/// check gzip and deflate binary frames are decoded into ticks
+fn check_compressed_frames() -> WSResult<()> {
+    let ticker = br#"{"s":"BTCUSDT","c":"28933.33"}"#;
+
+    let mut gzip = GzEncoder::new(vec![], flate2::Compression::default());
+    gzip.write_all(ticker)?;
+    let gzip = Message::Binary(gzip.finish()?);
+    let updates = message_parser(&Binance, Compression::Gzip, Ok(gzip))?;
+    assert!(matches!(&updates[..], [Update::Tick(tick)] if tick.price == dec!(28933.33)));
+
+    let mut deflate = DeflateEncoder::new(vec![], flate2::Compression::default());
+    deflate.write_all(ticker)?;
+    let deflate = Message::Binary(deflate.finish()?);
+    let updates = message_parser(&Binance, Compression::Deflate, Ok(deflate))?;
+    assert!(matches!(&updates[..], [Update::Tick(tick)] if tick.price == dec!(28933.33)));
+
+    let plain = Message::Binary(ticker.to_vec());
+    let updates = message_parser(&Binance, Compression::None, Ok(plain))?;
+    assert!(matches!(&updates[..], [Update::Tick(tick)] if tick.price == dec!(28933.33)));
+
+    Ok(())
+}
+
+#[test]
+/// check vwap weights prices by last trade size, counts a repeated last trade once and
+/// prefers collected trades
+fn check_vwap() {
+    let point = |price: Decimal, last_size: Option<Decimal>| PricesPairs {
+        name: "binance".to_string(),
+        price,
+        last_size,
+        ..Default::default()
+    };
+
+    let prices = vec![
+        point(dec!(100), Some(dec!(3))),
+        point(dec!(200), Some(dec!(1))),
+        point(dec!(1000), None),
+    ];
+    assert_eq!(vwap(&prices, &[]), Some(dec!(125)));
+    assert_eq!(vwap(&[point(dec!(100), None)], &[]), None);
+
+    // a chatty ticker repeating its last trade does not gain weight
+    let repeated = vec![
+        point(dec!(100), Some(dec!(3))),
+        point(dec!(200), Some(dec!(1))),
+        point(dec!(200), Some(dec!(1))),
+        point(dec!(200), Some(dec!(1))),
+    ];
+    assert_eq!(vwap(&repeated, &[]), Some(dec!(125)));
+
+    let trade = |price: Decimal, size: Decimal| Trade {
+        exchange: "binance".to_string(),
+        pair: "BTCUSDT".to_string(),
+        price,
+        size,
+        side: TradeSide::Buy,
+        trade_id: String::new(),
+        ts: 0,
+    };
+    let trades = vec![trade(dec!(100), dec!(1)), trade(dec!(300), dec!(1))];
+    assert_eq!(vwap(&prices, &trades), Some(dec!(200)));
+}
+
+#[test]
+/// check every aggregation method and its parsing
+fn check_aggregation() {
+    let point = |price: Decimal, received_mono: u64| PricesPairs {
+        name: "okx".to_string(),
+        price,
+        received_mono,
+        ..Default::default()
+    };
+    let prices = vec![
+        point(dec!(10), 0),
+        point(dec!(20), 30),
+        point(dec!(12), 40),
+        point(dec!(1000), 50),
+    ];
+
+    let aggregate = |method: &str| {
+        let aggregation: Aggregation = method.parse().expect("valid aggregation");
+        aggregation.aggregate(&prices, 100)
+    };
+    assert_eq!(aggregate("mean"), Some(dec!(260.5)));
+    assert_eq!(aggregate("median"), Some(dec!(16)));
+    assert_eq!(aggregate("trimmed:25"), Some(dec!(16)));
+    assert_eq!(aggregate("last"), Some(dec!(1000)));
+    // 10 stood 30, 20 stood 10, 12 stood 10 and 1000 stood 50 of 100
+    assert_eq!(aggregate("twap"), Some(dec!(506.2)));
+
+    assert!("trimmed:50".parse::<Aggregation>().is_err());
+    assert!("mode".parse::<Aggregation>().is_err());
+    assert_eq!(Aggregation::Mean.aggregate(&[], 0), None);
+}
+
+#[test]
+/// check per exchange statistics and the spread between exchanges
+fn check_exchange_stats() {
+    let point = |name: &str, price: Decimal| PricesPairs {
+        name: name.to_string(),
+        price,
+        ..Default::default()
+    };
+    let prices = vec![
+        point("binance", dec!(99)),
+        point("binance", dec!(101)),
+        point("okx", dec!(102)),
+        point("okx", dec!(104)),
+    ];
+
+    let stats = exchange_stats(&prices);
+    assert_eq!(stats["binance"].count, 2);
+    assert_eq!(stats["binance"].mean, dec!(100));
+    assert_eq!(stats["binance"].min, dec!(99));
+    assert_eq!(stats["binance"].max, dec!(101));
+    assert_eq!(stats["binance"].last, dec!(101));
+    assert_eq!(stats["binance"].stddev, dec!(1));
+
+    let spread = spread(&stats).expect("two exchanges");
+    assert_eq!(
+        (spread.high.as_str(), spread.low.as_str()),
+        ("okx", "binance")
+    );
+    assert_eq!(spread.absolute, dec!(3));
+    assert_eq!(spread.bps, dec!(3) / dec!(101.5) * dec!(10000));
+
+    assert_eq!(
+        super::aggregate::spread(&exchange_stats(&prices[..2])),
+        None
+    );
+}
+
+#[test]
+/// check outlier filters reject points around the cross exchange median and keep them
+fn check_outlier_filter() {
+    let point = |name: &str, price: Decimal| PricesPairs {
+        name: name.to_string(),
+        price,
+        ..Default::default()
+    };
+    let prices = vec![
+        point("binance", dec!(100)),
+        point("binance", dec!(101)),
+        point("coinbase", dec!(99)),
+        point("okx", dec!(100.5)),
+        point("okx", dec!(0)),
+        point("okx", dec!(150)),
+    ];
+    let rejected = |filter: &str| {
+        let filter: OutlierFilter = filter.parse().expect("valid filter");
+        let mut prices = prices.clone();
+        filter.reject(&mut prices);
+        prices
+            .iter()
+            .filter(|p| p.rejected.is_some())
+            .map(|p| p.price)
+            .collect::<Vec<Decimal>>()
+    };
+
+    assert_eq!(rejected("none"), vec![dec!(0)]);
+    assert_eq!(rejected("mad:3.5"), vec![dec!(0), dec!(150)]);
+    assert_eq!(rejected("pct:0.75"), vec![dec!(99), dec!(0), dec!(150)]);
+
+    let mut points = prices.clone();
+    OutlierFilter::Percent { percent: 5.0 }.reject(&mut points);
+    assert_eq!(points.len(), prices.len());
+    assert_eq!(points[4].rejected.as_deref(), Some("non-positive price"));
+
+    // a chatty off-market venue does not move the cross-venue center
+    let mut chatty = vec![
+        point("binance", dec!(100)),
+        point("binance", dec!(100.2)),
+        point("coinbase", dec!(100.1)),
+    ];
+    chatty.extend((0..10).map(|_| point("okx", dec!(150))));
+    for filter in ["mad:3.5", "pct:2"] {
+        let mut points = chatty.clone();
+        filter
+            .parse::<OutlierFilter>()
+            .expect("valid filter")
+            .reject(&mut points);
+        for point in &points {
+            assert_eq!(point.rejected.is_some(), point.name == "okx", "{filter}");
+        }
+    }
+
+    assert!("mad".parse::<OutlierFilter>().is_err());
+    assert!("pct:-1".parse::<OutlierFilter>().is_err());
+}
+
+#[test]
+/// check pairs without prices get no aggregate, the no data state and the missing exchanges
+fn check_no_data_pairs() -> WSResult<()> {
+    let empty = |prices: Vec<PricesPairs>| PairsCache {
+        prices,
+        aggregate: Some(dec!(0)),
+        state: PairState::Ok,
+        ..Default::default()
+    };
+    let point = PricesPairs {
+        name: "okx".to_string(),
+        price: dec!(100),
+        ..Default::default()
+    };
+
+    let mut pairs = HashMap::new();
+    pairs.insert("BTCUSDX".to_string(), empty(vec![]));
+    pairs.insert("BTCUSDT".to_string(), empty(vec![point]));
+    let exchanges = vec!["binance".to_string(), "okx".to_string()];
+    let pairs = aggregate_pairs_cache(
+        pairs,
+        &exchanges,
+        &HashMap::new(),
+        Aggregation::Mean,
+        OutlierFilter::None,
+        None,
+        None,
+    );
+
+    assert_eq!(pairs["BTCUSDX"].aggregate, None);
+    assert_eq!(pairs["BTCUSDX"].state, PairState::NoData);
+    assert_eq!(pairs["BTCUSDX"].missing, exchanges);
+    assert_eq!(pairs["BTCUSDT"].aggregate, Some(dec!(100)));
+    assert_eq!(pairs["BTCUSDT"].state, PairState::Ok);
+    assert_eq!(pairs["BTCUSDT"].missing, vec!["binance".to_string()]);
+
+    // prices without quotes leave only the mid aggregate empty, the pair still has data
+    let mut quoteless = HashMap::new();
+    quoteless.insert("BTCUSDT".to_string(), pairs["BTCUSDT"].clone());
+    let quoteless = aggregate_pairs_cache(
+        quoteless,
+        &exchanges,
+        &HashMap::new(),
+        Aggregation::Mid,
+        OutlierFilter::None,
+        None,
+        None,
+    );
+    assert_eq!(quoteless["BTCUSDT"].aggregate, None);
+    assert_eq!(quoteless["BTCUSDT"].state, PairState::Ok);
+
+    // no data is written explicitly and reads back
+    let content = serde_json::to_string(&pairs["BTCUSDX"])?;
+    assert!(content.contains("\"aggregate\":null"));
+    assert!(content.contains("\"state\":\"no_data\""));
+    let read: PairsCache = serde_json::from_str(&content)?;
+    assert_eq!(read, pairs["BTCUSDX"]);
+
+    Ok(())
+}
+
+#[test]
+/// check prices keep the exchange string precision and aggregate exactly
+fn check_decimal_precision() -> WSResult<()> {
+    let ticks = Binance.parse_message(json!({"s": "PEPEUSDT", "c": "0.00001230"}))?;
+    assert_eq!(ticks[0].price.to_string(), "0.00001230");
+
+    let mut pairs_cache = HashMap::new();
+    insert_pairs(vec!["pepe_usdt".to_string()], &mut pairs_cache);
+    for price in ["0.1", "0.2", "0.00001230"] {
+        let ticks = Binance.parse_message(json!({"s": "PEPEUSDT", "c": price}))?;
+        handle_response(&mut pairs_cache, "binance", ticks);
+    }
+    let pairs = aggregate_pairs_cache(
+        pairs_cache,
+        &["binance".to_string()],
+        &HashMap::new(),
+        Aggregation::Median,
+        OutlierFilter::None,
+        None,
+        None,
+    );
+    assert_eq!(pairs["PEPEUSDT"].aggregate, Some(dec!(0.1)));
+    assert_eq!(
+        Aggregation::Mean.aggregate(&pairs["PEPEUSDT"].prices[..2], 0),
+        Some(dec!(0.15))
+    );
+
+    // prices are written as the decimal strings the exchange sent
+    let content = serde_json::to_string(&pairs["PEPEUSDT"])?;
+    assert!(content.contains("\"price\":\"0.00001230\""));
+    let read: PairsCache = serde_json::from_str(&content)?;
+    assert_eq!(read.prices[2].price.to_string(), "0.00001230");
+
+    Ok(())
+}
+
+#[test]
+/// check prices roll into aligned OHLC bars per exchange and across exchanges
+fn check_bars() {
+    let point = |name: &str, price: Decimal, event_time: u64, last_size: Decimal| PricesPairs {
+        name: name.to_string(),
+        price,
+        event_time: Some(event_time),
+        last_size: Some(last_size),
+        ..Default::default()
+    };
+    let prices = vec![
+        point("binance", dec!(101), 1_000_400, dec!(1)),
+        point("binance", dec!(100), 1_000_100, dec!(2)),
+        point("okx", dec!(103), 1_000_900, dec!(0.5)),
+        point("binance", dec!(99), 1_000_700, dec!(1)),
+        point("okx", dec!(104), 1_002_100, dec!(0.25)),
+    ];
+
+    let bars = bars(&prices, NonZeroU64::new(1000).expect("non-zero interval"));
+    assert_eq!(bars.interval_ms, 1000);
+    assert_eq!(
+        bars.composite,
+        vec![
+            Bar {
+                start: 1_000_000,
+                open: dec!(100),
+                high: dec!(103),
+                low: dec!(99),
+                close: dec!(103),
+                volume: dec!(4.5),
+                ticks: 4,
+            },
+            Bar {
+                start: 1_002_000,
+                open: dec!(104),
+                high: dec!(104),
+                low: dec!(104),
+                close: dec!(104),
+                volume: dec!(0.25),
+                ticks: 1,
+            },
+        ]
+    );
+    assert_eq!(bars.by_exchange["binance"].len(), 1);
+    assert_eq!(bars.by_exchange["binance"][0].close, dec!(99));
+    assert_eq!(bars.by_exchange["okx"].len(), 2);
+
+    // intervals below a millisecond get no bars or candles instead of dividing by zero
+    let mut pairs = HashMap::new();
+    insert_pairs(vec!["btc_usdt".to_string()], &mut pairs);
+    pairs.get_mut("BTCUSDT").expect("pair cache").prices = prices;
+    let subscribed_at = HashMap::from([("okx".to_string(), 0)]);
+    let pairs = aggregate_pairs_cache(
+        pairs,
+        &["okx".to_string()],
+        &subscribed_at,
+        Aggregation::Mean,
+        OutlierFilter::None,
+        Some(Duration::ZERO),
+        Some(Duration::from_micros(500)),
+    );
+    assert_eq!(pairs["BTCUSDT"].bars, None);
+    assert!(pairs["BTCUSDT"].candles.is_empty());
+}
+
+#[test]
+/// check every exchange captures top of book and the pair gets mid, microprice and bbo
+fn check_quotes_and_bbo() -> WSResult<()> {
+    let binance = Binance.parse_message(json!({
+        "s": "BTCUSDT", "c": "100.5", "b": "100", "B": "3", "a": "101", "A": "1"
+    }))?;
+    let coinbase = Coinbase.parse_message(json!({
+        "type": "ticker", "product_id": "BTC-USDT", "price": "100.2",
+        "best_bid": "100.1", "best_bid_size": "0.5", "best_ask": "100.3", "best_ask_size": "0.5"
+    }))?;
+    let okx = Okx.parse_message(json!({"data": [{
+        "instId": "BTC-USDT", "last": "100.9",
+        "bidPx": "99.9", "bidSz": "2", "askPx": "", "askSz": ""
+    }]}))?;
+    assert_eq!(
+        binance[0].quote,
+        Some(Quote {
+            bid: dec!(100),
+            ask: dec!(101),
+            bid_size: Some(dec!(3)),
+            ask_size: Some(dec!(1)),
+        })
+    );
+    // a one sided book is no quote
+    assert_eq!(okx[0].quote, None);
+
+    let mut pairs_cache = HashMap::new();
+    insert_pairs(vec!["btc_usdt".to_string()], &mut pairs_cache);
+    handle_response(&mut pairs_cache, "binance", binance);
+    handle_response(&mut pairs_cache, "coinbase", coinbase);
+    handle_response(&mut pairs_cache, "okx", okx);
+    let prices = &pairs_cache["BTCUSDT"].prices;
+
+    // binance mid 100.5 and coinbase mid 100.2
+    assert_eq!(Aggregation::Mid.aggregate(prices, 0), Some(dec!(100.35)));
+    // binance leans to the ask with more bid size: (100 * 1 + 101 * 3) / 4 = 100.75
+    assert_eq!(
+        Aggregation::Microprice.aggregate(prices, 0),
+        Some(dec!(100.475))
+    );
+
+    let bbo = bbo(prices).expect("quotes");
+    assert_eq!(
+        (bbo.bid, bbo.bid_exchange.as_str()),
+        (dec!(100.1), "coinbase")
+    );
+    assert_eq!(
+        (bbo.ask, bbo.ask_exchange.as_str()),
+        (dec!(100.3), "coinbase")
+    );
+    assert_eq!(bbo.bid_size, Some(dec!(0.5)));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check okx books are validated by checksum and resubscribed when it does not match
+async fn check_okx_book_checksum() -> WSResult<()> {
+    let snapshot = r#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"snapshot","data":[{
+        "bids":[["3366.1","7","0","3"],["3366","6","3","4"]],
+        "asks":[["3366.8","9","10","3"],["3368","8","3","4"]],
+        "checksum":-1881014294,"prevSeqId":-1,"seqId":10}]}"#;
+    let update = r#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{
+        "bids":[],"asks":[["3367","2","0","1"]],
+        "checksum":-2066564521,"prevSeqId":10,"seqId":11}]}"#;
+    let corrupt = r#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{
+        "bids":[["3366","0","0","0"]],"asks":[],
+        "checksum":1,"prevSeqId":11,"seqId":12}]}"#;
+    let (url, _) = stub_server(vec![vec![snapshot, update, corrupt], vec![snapshot]]).await?;
+
+    let mut config = read_ws_details("../ws_details.json")?["okx"].clone();
+    config.ws_base_url = url;
+    let reconnect = ReconnectConfig {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(50),
+    };
+    let handler = WSHandler::new(&config, Box::new(Okx), vec!["btc_usdt".to_string()])
+        .with_reconnect(reconnect)
+        .with_channels(Channels {
+            depth: Some(5),
+            ..Default::default()
+        });
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let (_shutdown, shutdown) = watch::channel(false);
+    tokio::spawn(handler.read_loop(sender, shutdown));
+
+    assert!(matches!(
+        events.recv().await,
+        Some(SocketEvent::Subscribed { .. })
+    ));
+    let mut depths = vec![];
+    for _ in 0..2 {
+        match events.recv().await {
+            Some(SocketEvent::Depth { pair, depth, .. }) if pair == "BTCUSDT" => depths.push(depth),
+            event => panic!("expected depth, got {:?}", event),
+        }
+    }
+    assert_eq!(depths[0].sequence, Some(10));
+    assert_eq!(depths[1].sequence, Some(11));
+    assert_eq!(
+        depths[1].asks.iter().map(|l| l.price).collect::<Vec<_>>(),
+        vec![dec!(3366.8), dec!(3367), dec!(3368)]
+    );
+
+    match events.recv().await {
+        Some(SocketEvent::Reconnect(reconnect)) => assert!(reconnect.reason.contains("checksum")),
+        event => panic!("expected reconnect, got {:?}", event),
+    }
+    assert!(matches!(
+        events.recv().await,
+        Some(SocketEvent::Depth { depth, .. }) if depth.sequence == Some(10)
+    ));
+
+    Ok(())
+}
+
+#[test]
+/// check binance diffs follow the snapshot update ids and books consolidate by price
+fn check_book_sequence_and_depth() -> WSResult<()> {
+    let level = |price: Decimal, size: Decimal| DepthLevel { price, size };
+    let diff = |first: u64, last: u64, bids: Vec<DepthLevel>| BookUpdate {
+        pair: "BTCUSDT".to_string(),
+        action: BookAction::Update,
+        bids,
+        asks: vec![],
+        first_sequence: Some(first),
+        sequence: Some(last),
+        prev_sequence: None,
+        checksum: None,
+    };
+
+    let mut book = OrderBook::default();
+    book.apply(&BookUpdate {
+        action: BookAction::Snapshot,
+        asks: vec![level(dec!(101), dec!(1))],
+        first_sequence: None,
+        ..diff(
+            0,
+            100,
+            vec![level(dec!(100), dec!(1)), level(dec!(99), dec!(2))],
+        )
+    })?;
+
+    // diffs already in the snapshot are skipped, the first new one may overlap it
+    assert!(!book.apply(&diff(95, 100, vec![level(dec!(100), dec!(5))]))?);
+    assert!(book.apply(&diff(98, 103, vec![level(dec!(100), dec!(0))]))?);
+    assert!(book.apply(&diff(104, 104, vec![level(dec!(98), dec!(3))]))?);
+    assert!(matches!(
+        book.apply(&diff(106, 107, vec![])),
+        Err(WSError::BookOutOfSync(_))
+    ));
+
+    let binance = book.depth(2, 0);
+    assert_eq!(binance.sequence, Some(104));
+    assert_eq!(
+        binance.bids,
+        vec![level(dec!(99), dec!(2)), level(dec!(98), dec!(3))]
+    );
+
+    let mut depth = Depth::default();
+    depth.by_exchange.insert("binance".to_string(), binance);
+    depth.by_exchange.insert(
+        "okx".to_string(),
+        BookDepth {
+            bids: vec![level(dec!(99), dec!(1.5))],
+            asks: vec![level(dec!(100.5), dec!(1))],
+            sequence: None,
+            updated_at: 0,
+        },
+    );
+    depth.consolidate();
+    assert_eq!(
+        depth.bids,
+        vec![level(dec!(99), dec!(3.5)), level(dec!(98), dec!(3))]
+    );
+    assert_eq!(
+        depth.asks,
+        vec![level(dec!(100.5), dec!(1)), level(dec!(101), dec!(1))]
+    );
+
+    Ok(())
+}
+
+#[test]
+/// check trades of every exchange are normalized with the taker side and kept per pair
+fn check_trades() -> WSResult<()> {
+    let updates = |exchange: &dyn Exchange, msg: &str| {
+        message_parser(
+            exchange,
+            Compression::None,
+            Ok(Message::Text(msg.to_string())),
+        )
+    };
+    let binance = updates(
+        &Binance,
+        r#"{"e":"trade","E":1,"s":"BTCUSDT","t":12345,"p":"28933.33","q":"0.5","T":1666222102061,"m":true}"#,
+    )?;
+    let coinbase = updates(
+        &Coinbase,
+        r#"{"type":"match","trade_id":10,"product_id":"BTC-USDT","size":"1.25","price":"28933.10","side":"sell","time":"2022-10-19T23:28:22.061Z"}"#,
+    )?;
+    let okx = updates(
+        &Okx,
+        r#"{"arg":{"channel":"trades","instId":"BTC-USDT"},"data":[{"instId":"BTC-USDT","tradeId":"130639474","px":"28933.2","sz":"0.1","side":"sell","ts":"1666222102061","count":"3"}]}"#,
+    )?;
+
+    let trades: Vec<Trade> = [binance, coinbase, okx]
+        .into_iter()
+        .flatten()
+        .map(|update| match update {
+            Update::Trade(trade) => trade,
+            update => panic!("expected trade, got {:?}", update),
+        })
+        .collect();
+    assert_eq!(
+        trades[0],
+        Trade {
+            exchange: "binance".to_string(),
+            pair: "BTCUSDT".to_string(),
+            price: dec!(28933.33),
+            size: dec!(0.5),
+            side: TradeSide::Sell,
+            trade_id: "12345".to_string(),
+            ts: 1_666_222_102_061,
+        }
+    );
+    // coinbase reports the maker side, the taker bought from a sell order
+    assert_eq!(trades[1].side, TradeSide::Buy);
+    assert_eq!(trades[1].ts, 1_666_222_102_061);
+    assert_eq!(trades[2].side, TradeSide::Sell);
+    assert_eq!(trades[2].trade_id, "130639474");
+
+    let mut cache = ExchangesCache::default();
+    insert_pairs(vec!["btc_usdt".to_string()], &mut cache.pairs);
+    handle_socket_event(
+        &mut cache,
+        SocketEvent::Trades {
+            name: "okx".to_string(),
+            trades: trades.clone(),
+        },
+    );
+    assert_eq!(cache.pairs["BTCUSDT"].trades, trades);
+
+    Ok(())
+}
+
+#[test]
+/// check candles are decoded per exchange and in progress updates collapse into the closed candle
+fn check_candles() -> WSResult<()> {
+    let candles = |exchange: &dyn Exchange, msg: &str| -> WSResult<Vec<Candle>> {
+        let updates = message_parser(
+            exchange,
+            Compression::None,
+            Ok(Message::Text(msg.to_string())),
+        )?;
+        Ok(updates
+            .into_iter()
+            .map(|update| match update {
+                Update::Candle(candle) => candle,
+                update => panic!("expected candle, got {:?}", update),
+            })
+            .collect())
+    };
+    let kline = |close: &str, closed: bool| {
+        format!(
+            r#"{{"e":"kline","E":1,"s":"BTCUSDT","k":{{"t":1666222080000,"T":1666222139999,"s":"BTCUSDT","i":"1m","o":"28930","c":"{close}","h":"28940","l":"28920","v":"12.5","x":{closed}}}}}"#
+        )
+    };
+
+    assert_eq!(
+        Binance.kline_channel(Duration::from_secs(60)),
+        Some("kline_1m".to_string())
+    );
+    assert_eq!(
+        Okx.kline_channel(Duration::from_secs(3600)),
+        Some("candle1H".to_string())
+    );
+    assert_eq!(Coinbase.kline_channel(Duration::from_secs(60)), None);
+    assert_eq!(Binance.kline_channel(Duration::from_secs(7)), None);
+
+    let mut cache = ExchangesCache::default();
+    insert_pairs(vec!["btc_usdt".to_string()], &mut cache.pairs);
+    for (close, closed) in [("28931", false), ("28935", false), ("28933", true)] {
+        let candles = candles(&Binance, &kline(close, closed))?;
+        handle_socket_event(
+            &mut cache,
+            SocketEvent::Candles {
+                name: "binance".to_string(),
+                candles,
+            },
+        );
+    }
+    // the first okx candle is closed by the one after it, the second is still in progress
+    for (ts, confirm) in [("1666222080000", "0"), ("1666222140000", "0")] {
+        let msg = format!(
+            r#"{{"arg":{{"channel":"candle1m","instId":"BTC-USDT"}},"data":[["{ts}","28930.1","28941","28921","28932.4","3.2","92563","92563","{confirm}"]]}}"#
+        );
+        let candles = candles(&Okx, &msg)?;
+        handle_socket_event(
+            &mut cache,
+            SocketEvent::Candles {
+                name: "okx".to_string(),
+                candles,
+            },
+        );
+    }
+    assert_eq!(cache.pairs["BTCUSDT"].candles.len(), 3);
+
+    let pairs = aggregate_pairs_cache(
+        cache.pairs,
+        &["binance".to_string(), "okx".to_string()],
+        &HashMap::new(),
+        Aggregation::Mean,
+        OutlierFilter::None,
+        None,
+        Some(Duration::from_secs(60)),
+    );
+    let candles = &pairs["BTCUSDT"].candles;
+    assert_eq!(
+        candles[0],
+        Candle {
+            exchange: "binance".to_string(),
+            pair: "BTCUSDT".to_string(),
+            start: 1_666_222_080_000,
+            interval_ms: 60_000,
+            open: dec!(28930),
+            high: dec!(28940),
+            low: dec!(28920),
+            close: dec!(28933),
+            volume: dec!(12.5),
+            closed: true,
+            resampled: false,
+        }
+    );
+    assert_eq!(candles.len(), 2);
+    assert_eq!(candles[1].exchange, "okx");
+    assert_eq!(candles[1].start, 1_666_222_080_000);
+    assert_eq!(candles[1].volume, dec!(3.2));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check a failing candle socket reports its own status and leaves the ticker socket ok
+async fn check_candle_socket_status() -> WSResult<()> {
+    let (ticker_url, _) = stub_server(vec![vec![
+        r#"{"arg":{"channel":"tickers","instId":"BTC-USDT"},"data":[{"instId":"BTC-USDT","last":"30000"}]}"#,
+    ]])
+    .await?;
+    let (candle_url, _) = stub_server(vec![vec![r#"{"event":"error","code":"60012"}"#]]).await?;
+    let mut okx = read_ws_details("../ws_details.json")?["okx"].clone();
+    okx.ws_base_url = ticker_url;
+    okx.candle_ws_url = Some(candle_url);
+    let path = std::env::temp_dir().join(format!("ws_socket_details_{}.json", std::process::id()));
+    std::fs::write(&path, serde_json::to_string(&vec![okx])?)?;
+
+    let config = CollectorConfig {
+        pairs: vec!["btc_usdt".to_string()],
+        config_path: path.to_str().expect("utf-8 path").to_string(),
+        duration: Duration::from_millis(300),
+        channels: Channels {
+            kline: Some(Duration::from_secs(60)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let cache = run(&config).await;
+    std::fs::remove_file(&path)?;
+    let cache = cache?;
+
+    assert_eq!(cache.exchanges["okx"].status, ExchangeState::Ok);
+    assert_eq!(cache.exchanges["okx:candles"].status, ExchangeState::Failed);
+    assert!(cache.pairs["BTCUSDT"].missing.is_empty());
+
+    Ok(())
+}
+
+#[test]
+/// check exchanges without candle channel get candles resampled inside the collection window
+fn check_resampled_candles() {
+    let point = |price: Decimal, event_time: u64| PricesPairs {
+        name: "coinbase".to_string(),
+        price,
+        event_time: Some(event_time),
+        received_at: event_time,
+        last_size: Some(dec!(0.5)),
+        ..Default::default()
+    };
+    let prices = vec![
+        point(dec!(99), 59_000),
+        point(dec!(100), 60_000),
+        point(dec!(102), 61_000),
+        point(dec!(101), 119_999),
+        point(dec!(103), 120_500),
+    ];
+
+    // the first candle started before the collection and the last one ends after it
+    let minute = NonZeroU64::new(60_000).expect("non-zero interval");
+    let candles = resampled_candles(&prices, "BTCUSD", minute, (30_000, 150_000));
+    assert_eq!(
+        candles,
+        vec![Candle {
+            exchange: "coinbase".to_string(),
+            pair: "BTCUSD".to_string(),
+            start: 60_000,
+            interval_ms: 60_000,
+            open: dec!(100),
+            high: dec!(102),
+            low: dec!(100),
+            close: dec!(101),
+            volume: dec!(1.5),
+            closed: true,
+            resampled: true,
+        }]
+    );
+
+    // the window opens when the exchange subscribed, not when the process started
+    let mut pairs = HashMap::new();
+    insert_pairs(vec!["btc_usd".to_string()], &mut pairs);
+    pairs.get_mut("BTCUSD").expect("pair cache").prices = prices;
+    let subscribed_at = HashMap::from([("coinbase".to_string(), 61_000)]);
+    let pairs = aggregate_pairs_cache(
+        pairs,
+        &["coinbase".to_string()],
+        &subscribed_at,
+        Aggregation::Mean,
+        OutlierFilter::None,
+        None,
+        Some(Duration::from_secs(60)),
+    );
+    let starts: Vec<u64> = pairs["BTCUSD"].candles.iter().map(|c| c.start).collect();
+    assert_eq!(starts, vec![120_000]);
+}
+
+#[test]
+/// check instruments map to native symbols and back, with config overrides
+fn check_instrument_symbols() -> WSResult<()> {
+    let mut ws_details = read_ws_details("../ws_details.json")?;
+    let instrument = Instrument::parse("btc_usdt").expect("valid pair");
+    assert_eq!(
+        instrument,
+        Instrument {
+            base: "BTC".to_string(),
+            quote: "USDT".to_string(),
+            kind: InstrumentKind::Spot,
+        }
+    );
+    assert_eq!(instrument.key(), "BTCUSDT");
+    assert_eq!(instrument.to_string(), "btc_usdt");
+    assert_eq!(Instrument::parse("btcusdt"), None);
+    assert_eq!(Instrument::parse("btc_"), None);
+
+    assert_eq!(Binance.native_symbol(&instrument), "BTCUSDT");
+    assert_eq!(Coinbase.native_symbol(&instrument), "BTC-USDT");
+    assert_eq!(Okx.native_symbol(&instrument), "BTC-USDT");
+    // symbols without a dash no longer panic
+    assert_eq!(pair_key("BTCUSDT"), "BTCUSDT");
+    assert_eq!(pair_key("btc-usdt"), "BTCUSDT");
+
+    // coinbase quotes bitcoin in usd, collect it deliberately as btc_usdt
+    let coinbase = ws_details.get_mut("coinbase").expect("coinbase config");
+    coinbase
+        .symbols
+        .insert("btc_usdt".to_string(), "BTC-USD".to_string());
+    let pairs = vec!["btc_usdt".to_string(), "eth_usdt".to_string()];
+    let subscribe = Coinbase.subscribe_message(coinbase, &pairs, &Channels::default())?;
+    assert!(subscribe.contains(r#""product_ids":["BTC-USD","ETH-USDT"]"#));
+
+    let symbols = SymbolMap::new(&Coinbase, coinbase, &pairs, &[]);
+    assert_eq!(symbols.native("btc_usdt"), Some("BTC-USD"));
+    assert_eq!(
+        symbols.instruments("BTC-USD"),
+        std::slice::from_ref(&instrument)
+    );
+    let updates = message_parser(
+        &Coinbase,
+        Compression::None,
+        Ok(Message::Text(
+            r#"{"type":"ticker","product_id":"BTC-USD","price":"28933.10"}"#.to_string(),
+        )),
+    )?;
+    let updates = symbols.normalize(updates[0].clone());
+    match &updates[0] {
+        Update::Tick(tick) => assert_eq!(tick.pair, "BTCUSDT"),
+        update => panic!("expected tick, got {:?}", update),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check pairs are listed with the preferred equivalent quote and converted at the live rate
+async fn check_quote_equivalence() -> WSResult<()> {
+    let (url, mut received) = stub_server(vec![vec![
+        r#"{"type":"ticker","product_id":"BTC-USD","price":"29900"}"#,
+        r#"{"type":"ticker","product_id":"USDT-USD","price":"1.25"}"#,
+        r#"{"type":"ticker","product_id":"BTC-USD","price":"30000","best_bid":"29990","best_ask":"30010"}"#,
+        r#"{"type":"match","trade_id":1,"product_id":"BTC-USD","price":"30000","size":"0.5","side":"sell","time":"2023-05-01T12:00:00.000000Z"}"#,
+        r#"{"type":"snapshot","product_id":"BTC-USD","bids":[["30000","1"]],"asks":[["30010","2"]]}"#,
+    ]])
+    .await?;
+    let mut config = read_ws_details("../ws_details.json")?["coinbase"].clone();
+    config.ws_base_url = url;
+    let groups = vec![vec!["USD".to_string(), "USDT".to_string()]];
+    let handler = WSHandler::new(&config, Box::new(Coinbase), vec!["btc_usdt".to_string()])
+        .with_quotes(&groups, true)
+        .with_channels(Channels {
+            trades: true,
+            depth: Some(5),
+            ..Default::default()
+        });
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let (_shutdown_sender, shutdown) = watch::channel(false);
+    tokio::spawn(handler.read_loop(sender, shutdown));
+
+    let subscribe = received.recv().await.expect("subscribe message");
+    assert!(subscribe.contains(r#""product_ids":["BTC-USD","USDT-USD"]"#));
+
+    // the first bitcoin price arrived before the rate and is skipped
+    let mut cache = ExchangesCache::default();
+    insert_pairs(vec!["btc_usdt".to_string()], &mut cache.pairs);
+    cache
+        .exchanges
+        .insert("coinbase".to_string(), ExchangeStatus::default());
+    handle_socket_event(&mut cache, events.recv().await.expect("subscribed event"));
+    assert!(cache.exchanges["coinbase"].subscribed_at.is_some());
+    for _ in 0..2 {
+        let event = events.recv().await.expect("ticks event");
+        handle_socket_event(&mut cache, event);
+    }
+    let prices = &cache.pairs["BTCUSDT"].prices;
+    assert_eq!(prices.len(), 1);
+    assert_eq!(prices[0].price, dec!(24000));
+    assert_eq!(prices[0].rate, Some(dec!(1.25)));
+    assert_eq!(prices[0].quote.map(|quote| quote.bid), Some(dec!(23992)));
+    assert_eq!(prices[0].instrument, Instrument::parse("btc_usd"));
+
+    // trades and order book levels are converted at the same rate
+    for _ in 0..2 {
+        let event = events.recv().await.expect("trades and depth events");
+        handle_socket_event(&mut cache, event);
+    }
+    let pair_cache = &cache.pairs["BTCUSDT"];
+    assert_eq!(pair_cache.trades[0].price, dec!(24000));
+    let depth = &pair_cache.depth.as_ref().expect("depth").by_exchange["coinbase"];
+    assert_eq!(depth.bids[0].price, dec!(24000));
+    assert_eq!(depth.asks[0].price, dec!(24008));
+
+    // without a group the requested quote is kept
+    let direct = Instrument::parse("btc_usdt").expect("valid pair");
+    assert_eq!(direct.listed_as(&[], &config.preferred_quotes), direct);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check pairs listed with the same native symbol are subscribed once and all get its prices
+async fn check_shared_native_symbol() -> WSResult<()> {
+    let (url, mut received) = stub_server(vec![vec![
+        r#"{"type":"ticker","product_id":"USDT-USD","price":"1.25"}"#,
+        r#"{"type":"ticker","product_id":"BTC-USD","price":"30000"}"#,
+    ]])
+    .await?;
+    let mut config = read_ws_details("../ws_details.json")?["coinbase"].clone();
+    config.ws_base_url = url;
+    let groups = vec![vec!["USD".to_string(), "USDT".to_string()]];
+    let pairs = vec!["btc_usdt".to_string(), "btc_usd".to_string()];
+
+    let symbols = SymbolMap::new(&Coinbase, &config, &pairs, &groups);
+    assert_eq!(symbols.pairs(), ["btc_usd"]);
+    assert_eq!(symbols.natives(), ["BTC-USD"]);
+    assert_eq!(symbols.instruments("BTCUSD").len(), 2);
+
+    let handler =
+        WSHandler::new(&config, Box::new(Coinbase), pairs.clone()).with_quotes(&groups, true);
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let (_shutdown_sender, shutdown) = watch::channel(false);
+    tokio::spawn(handler.read_loop(sender, shutdown));
+
+    let subscribe = received.recv().await.expect("subscribe message");
+    assert!(subscribe.contains(r#""product_ids":["BTC-USD","USDT-USD"]"#));
+
+    let mut cache = ExchangesCache::default();
+    insert_pairs(pairs, &mut cache.pairs);
+    for _ in 0..3 {
+        let event = events.recv().await.expect("ticks event");
+        handle_socket_event(&mut cache, event);
+    }
+    assert_eq!(cache.pairs["BTCUSD"].prices[0].price, dec!(30000));
+    assert_eq!(cache.pairs["BTCUSD"].prices[0].rate, None);
+    assert_eq!(cache.pairs["BTCUSDT"].prices[0].price, dec!(24000));
+    assert_eq!(cache.pairs["BTCUSDT"].prices[0].rate, Some(dec!(1.25)));
+
+    Ok(())
+}
+
+#[tokio::test]
+/// check synthetic prices are derived from fresh legs and carry the leg prices
+async fn check_synthetic_pairs() -> WSResult<()> {
+    let (url, mut received) = stub_server(vec![vec![
+        r#"{"s":"SOLUSDT","c":"150"}"#,
+        r#"{"s":"ETHUSDT","c":"3000"}"#,
+        r#"{"s":"SOLUSDT","c":"151.5"}"#,
+    ]])
+    .await?;
+    let mut config = read_ws_details("../ws_details.json")?["binance"].clone();
+    config.ws_base_url = url;
+
+    let sol_eth = Instrument::parse("sol_eth").expect("valid pair");
+    let synthetic = Synthetic::new(&sol_eth, "usdt", Duration::from_millis(40));
+    assert_eq!(
+        synthetic.base_leg,
+        Instrument::parse("sol_usdt").expect("valid pair")
+    );
+    assert_eq!(
+        synthetic.quote_leg,
+        Instrument::parse("eth_usdt").expect("valid pair")
+    );
+    let mut handler = WSHandler::new(&config, Box::new(Binance), vec![])
+        .with_synthetics(HashMap::from([(sol_eth.key(), synthetic)]));
+    handler.connect_and_subscribe().await?;
+    let subscribe = received.recv().await.expect("subscribe message");
+    assert!(subscribe.contains(r#""params":["SOLUSDT@ticker","ETHUSDT@ticker"]"#));
+
+    let ticks = |updates: Option<WSResult<Vec<Update>>>| -> Vec<Tick> {
+        updates
+            .expect("updates")
+            .expect("valid updates")
+            .into_iter()
+            .filter_map(|update| match update {
+                Update::Tick(tick) => Some(tick),
+                _ => None,
+            })
+            .collect()
+    };
+    assert_eq!(ticks(handler.next_updates().await).len(), 1);
+    // the sol leg is too old once the eth leg arrives
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(ticks(handler.next_updates().await).len(), 1);
+
+    let ticks = ticks(handler.next_updates().await);
+    assert_eq!(ticks.len(), 2);
+    assert_eq!(ticks[1].pair, "SOLETH");
+    assert_eq!(ticks[1].price, dec!(0.0505));
+    let legs = ticks[1].legs.expect("synthetic legs");
+    assert_eq!((legs.base, legs.quote), (dec!(151.5), dec!(3000)));
+    assert!(legs.age_ms <= 40);
+    // direct prices carry no legs
+    assert_eq!(ticks[0].legs, None);
+
+    Ok(())
+}
+
+/// serve the body to a single http request and return the base url
+async fn stub_http(body: String) -> WSResult<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}", listener.local_addr()?);
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.expect("accept failed");
+        let mut request = [0; 4096];
+        let _ = stream.read(&mut request).await;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream
+            .write_all(response.as_bytes())
+            .await
+            .expect("write failed");
+    });
+    Ok(url)
+}
+
+#[test]
+/// check the instrument lists keep only the symbols open for trading
+fn check_parse_instruments() -> WSResult<()> {
+    let binance = Binance.parse_instruments(json!({"symbols": [
+        {"symbol": "BTCUSDT", "status": "TRADING"},
+        {"symbol": "LUNAUSDT", "status": "BREAK"},
+    ]}))?;
+    assert_eq!(binance, vec!["BTCUSDT"]);
+
+    let coinbase = Coinbase.parse_instruments(json!([
+        {"id": "BTC-USD", "status": "online", "trading_disabled": false},
+        {"id": "ETH-USD", "status": "online", "trading_disabled": true},
+        {"id": "XYZ-USD", "status": "delisted"},
+    ]))?;
+    assert_eq!(coinbase, vec!["BTC-USD"]);
+
+    let okx = Okx.parse_instruments(json!({"code": "0", "data": [
+        {"instId": "BTC-USDT", "state": "live"},
+        {"instId": "ABC-USDT", "state": "suspend"},
+    ]}))?;
+    assert_eq!(okx, vec!["BTC-USDT"]);
+    Ok(())
+}
+
+#[tokio::test]
+/// check unlisted pairs are found from the fetched list, cached on disk and rejected
+async fn check_pair_validation() -> WSResult<()> {
+    let body = json!({"symbols": [{"symbol": "BTCUSDT", "status": "TRADING"}]}).to_string();
+    let mut config = read_ws_details("../ws_details.json")?["binance"].clone();
+    config.rest_base_url = Some(stub_http(body).await?);
+    let path =
+        std::env::temp_dir().join(format!("ws_socket_instruments_{}.json", std::process::id()));
+    let path = path.to_str().expect("utf-8 path");
+    let ttl = Duration::from_secs(60);
+
+    let mut instruments = read_instruments(path);
+    assert!(instruments.is_empty());
+    let listed = listed_symbols(&Binance, &config, &mut instruments, ttl)
+        .await?
+        .expect("binance lists instruments");
+    assert_eq!(listed, HashSet::from(["BTCUSDT".to_string()]));
+    write_instruments(path, &instruments)?;
+
+    // the stub served its only request, the list comes from the disk cache
+    let mut instruments = read_instruments(path);
+    let cached = listed_symbols(&Binance, &config, &mut instruments, ttl).await?;
+    assert_eq!(cached, Some(listed.clone()));
+    std::fs::remove_file(path)?;
+
+    let pairs = vec!["btc_usdt".to_string(), "foo_usdt".to_string()];
+    let handler = WSHandler::new(&config, Box::new(Binance), pairs);
+    assert_eq!(handler.unlisted_pairs(&listed), vec!["foo_usdt"]);
+    let handler = handler.without_pairs(&["foo_usdt".to_string()]);
+    assert_eq!(handler.pairs(), ["btc_usdt"]);
+    assert!(handler.unlisted_pairs(&listed).is_empty());
+
+    // the library stays offline unless validation is asked for
+    assert_eq!(
+        CollectorConfig::default().pair_validation,
+        PairValidation::Off
+    );
+    assert_eq!("reject".parse(), Ok(PairValidation::Reject));
+    assert!("strict".parse::<PairValidation>().is_err());
+    Ok(())
+}
@@ -1,23 +1,153 @@
-pub use clap::Parser;
 use futures_util::{SinkExt, StreamExt};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::net::TcpStream;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Duration,
+};
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, watch},
+    time,
+};
 use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
 
-use crate::{errors::WSError, helpers};
+use crate::{
+    aggregate::{Aggregation, OutlierFilter},
+    discovery::PairValidation,
+    errors::WSError,
+    exchanges::Exchange,
+    helpers, parser,
+};
 
-#[derive(Parser, Debug)]
-#[clap(author = "Nizamuddin", version, about)]
-/// Web socket argument structure
-pub struct Args {
-    /// Mode should be cache or read, cache collect pairs data and read show the cached data
-    #[clap(short, long)]
-    pub mode: String,
+#[derive(Debug, Clone)]
+/// Collector configuration used by `run` and `run_cache`
+pub struct CollectorConfig {
+    /// pairs to collect, ex. btc_usdt
+    pub pairs: Vec<String>,
+    /// exchange names to collect from, empty means every enabled exchange
+    pub exchanges: Vec<String>,
+    /// how long sockets are read before the cache is aggregated
+    pub duration: Duration,
+    /// file the cache is written to
+    pub output_path: String,
+    /// web socket configs file
+    pub config_path: String,
+    /// reconnect policy of every exchange socket
+    pub reconnect: ReconnectConfig,
+    /// keepalive and stale connection policy of every exchange socket
+    pub heartbeat: HeartbeatConfig,
+    /// stop early on Ctrl-C / SIGTERM and keep the data collected so far, off by default as
+    /// the handlers replace the signal handling of the whole process
+    pub handle_signals: bool,
+    /// aggregation of the pair prices
+    pub aggregation: Aggregation,
+    /// outlier filter applied before aggregation
+    pub outlier_filter: OutlierFilter,
+    /// `run_cache` fails after writing the cache when a pair ended without data
+    pub require_data: bool,
+    /// resample the prices into OHLC bars of this interval
+    pub bar_interval: Option<Duration>,
+    /// channels subscribed next to the ticker
+    pub channels: Channels,
+    /// equivalent quote coins, ex. `[["USD", "USDT", "USDC"]]`, exchanges may list a pair
+    /// with their preferred quote of the group instead
+    pub quote_groups: Vec<Vec<String>>,
+    /// convert prices of an equivalent quote at the live rate, subscribed on the same exchange
+    pub convert_quotes: bool,
+    /// pairs derived from two legs against the bridge coin instead of subscribed, ex. sol_eth
+    pub synthetic_pairs: Vec<String>,
+    /// coin both legs of a synthetic pair are quoted in
+    pub bridge: String,
+    /// legs older than this are not combined into a synthetic price
+    pub max_leg_staleness: Duration,
+    /// check of the pairs against the instrument lists of the exchanges before connecting
+    pub pair_validation: PairValidation,
+    /// file the instrument lists are cached in
+    pub instruments_path: String,
+    /// how long cached instrument lists are used before they are fetched again
+    pub instruments_ttl: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// channels subscribed next to the ticker
+pub struct Channels {
+    /// order book channel, keeps this many levels per side in the output
+    pub depth: Option<usize>,
+    /// public trades channel
+    pub trades: bool,
+    /// candle channel of this interval, exchanges without one get candles resampled from the ticker
+    pub kline: Option<Duration>,
+    /// subscribe the candle channel alone, used for the socket of `candle_ws_url`
+    pub candles_only: bool,
+}
+
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        CollectorConfig {
+            pairs: vec![],
+            exchanges: vec![],
+            duration: Duration::from_secs(10),
+            output_path: "exchanges.json".to_string(),
+            config_path: "ws_details.json".to_string(),
+            reconnect: ReconnectConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            handle_signals: false,
+            aggregation: Aggregation::default(),
+            outlier_filter: OutlierFilter::default(),
+            require_data: false,
+            bar_interval: None,
+            channels: Channels::default(),
+            quote_groups: vec![],
+            convert_quotes: false,
+            synthetic_pairs: vec![],
+            bridge: "USDT".to_string(),
+            max_leg_staleness: Duration::from_secs(5),
+            pair_validation: PairValidation::default(),
+            instruments_path: "instruments.json".to_string(),
+            instruments_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// reconnect policy of a web socket handler
+pub struct ReconnectConfig {
+    /// consecutive failed attempts before the exchange gives up
+    pub max_attempts: u32,
+    /// delay before the first attempt, doubled after every failed attempt
+    pub initial_backoff: Duration,
+    /// upper bound of the delay
+    pub max_backoff: Duration,
+}
 
-    /// Pairs should collect coins with pair
-    #[clap(short, long, default_value = "")]
-    pub pairs: String,
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// heartbeat policy of a web socket handler
+pub struct HeartbeatConfig {
+    /// idle time before the exchange keepalive message is sent
+    pub keepalive_interval: Duration,
+    /// idle time before the connection is considered stale and reconnected
+    pub stale_timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            keepalive_interval: Duration::from_secs(25),
+            stale_timeout: Duration::from_secs(60),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -26,185 +156,1145 @@ pub struct WebSocketConfig {
     pub name: String,
     pub ws_base_url: String,
     pub req_param: Value,
+    /// disabled exchanges are skipped in cache mode
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// compression of binary frames sent by the exchange
+    #[serde(default)]
+    pub compression: Compression,
+    /// rest api url, used for order book snapshots and instrument lists
+    #[serde(default)]
+    pub rest_base_url: Option<String>,
+    /// web socket url serving the candle channel, when it is not `ws_base_url`
+    #[serde(default)]
+    pub candle_ws_url: Option<String>,
+    /// native symbol overrides by pair, ex. `{"btc_usdt": "BTC-USD"}`
+    #[serde(default)]
+    pub symbols: HashMap<String, String>,
+    /// quote coins used in place of an equivalent requested quote, in order of preference
+    #[serde(default)]
+    pub preferred_quotes: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-/// coinbase request parameter structure
-pub struct BinanceReqParam {
-    pub method: String,
-    pub params: Vec<String>,
-    pub id: i32,
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// instrument list of an exchange cached on disk
+pub struct ExchangeInstruments {
+    /// rest url the list was fetched from
+    pub url: String,
+    /// unix time in milliseconds the list was fetched
+    pub fetched_at: u64,
+    /// native symbols open for trading
+    pub symbols: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-/// coinbase request parameter structure
-pub struct CoinbaseReqParam {
-    #[serde(rename = "type")]
-    pub type_name: String,
-    pub channels: Vec<String>,
-    pub product_ids: Vec<String>,
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+/// exchange independent instrument, coins are uppercase
+pub struct Instrument {
+    pub base: String,
+    pub quote: String,
+    #[serde(default)]
+    pub kind: InstrumentKind,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-/// okex request parameter structure
-pub struct OkexReqParam {
-    pub op: String,
-    pub args: Vec<OkexReqParamArg>,
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+/// market type of an instrument
+pub enum InstrumentKind {
+    #[default]
+    Spot,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-/// okex request parameter argument structure
-pub struct OkexReqParamArg {
-    pub channel: String,
-    #[serde(rename = "instId")]
-    pub inst_id: String,
+#[derive(Debug, Clone, Default, PartialEq)]
+/// mapping between the requested pairs and the native symbols of one exchange
+pub struct SymbolMap {
+    /// pairs handed to the exchange, the quote may be replaced by an equivalent one
+    pub(crate) pairs: Vec<String>,
+    /// native symbol by pair, ex. btc_usdt -> BTC-USDT
+    pub(crate) natives: HashMap<String, String>,
+    /// instruments requested from a native symbol without separators, ex. BTCUSD is
+    /// requested as btc_usd and as btc_usdt
+    pub(crate) instruments: HashMap<String, Vec<Instrument>>,
+    /// instrument the exchange lists by native symbol without separators
+    pub(crate) listed: HashMap<String, Instrument>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+/// binary frame compression
+pub enum Compression {
+    /// binary frames hold plain utf-8 text
+    #[default]
+    None,
+    Gzip,
+    /// raw deflate without zlib header
+    Deflate,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+/// cache file structure
+pub struct ExchangesCache {
+    pub pairs: HashMap<String, PairsCache>,
+    /// socket reconnects, no data was received between disconnect and reconnect
+    pub reconnects: Vec<Reconnect>,
+    /// status of every exchange in the run
+    pub exchanges: HashMap<String, ExchangeStatus>,
+    /// run was stopped by a signal before the duration elapsed
+    #[serde(default)]
+    pub interrupted: bool,
+    /// unix time in milliseconds of monotonic receive time zero
+    #[serde(default)]
+    pub monotonic_epoch: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+/// data state of a pair after aggregation
+pub enum PairState {
+    #[default]
+    Ok,
+    /// no accepted price arrived, the pair has no aggregate
+    NoData,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+/// exchange health in a run
+pub enum ExchangeState {
+    #[default]
+    Ok,
+    Degraded,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+/// exchange status block of the cache file
+pub struct ExchangeStatus {
+    pub status: ExchangeState,
+    /// last reason the exchange was degraded or failed
+    pub reason: Option<String>,
+    /// unix time in milliseconds the exchange first subscribed, resampled candles start there
+    #[serde(default)]
+    pub subscribed_at: Option<u64>,
+}
+
+impl ExchangeStatus {
+    /// mark degraded unless the exchange already failed
+    pub fn degrade(&mut self, reason: String) {
+        if self.status != ExchangeState::Failed {
+            self.status = ExchangeState::Degraded;
+            self.reason = Some(reason);
+        }
+    }
+
+    /// mark failed
+    pub fn fail(&mut self, reason: String) {
+        self.status = ExchangeState::Failed;
+        self.reason = Some(reason);
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// reconnect of an exchange socket
+pub struct Reconnect {
+    pub name: String,
+    pub reason: String,
+    /// unix time in milliseconds the socket dropped
+    pub disconnected_at: u64,
+    /// unix time in milliseconds the socket was subscribed again, none when it gave up
+    pub reconnected_at: Option<u64>,
+    pub attempts: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 /// pairs cache structure
 pub struct PairsCache {
     pub prices: Vec<PricesPairs>,
-    pub aggregate: f64,
+    /// aggregate of the accepted prices, none when the pair has no data or mid and
+    /// microprice find no quote
+    pub aggregate: Option<Decimal>,
+    #[serde(default)]
+    pub state: PairState,
+    /// exchanges that sent no price for the pair
+    #[serde(default)]
+    pub missing: Vec<String>,
+    /// method and parameters `aggregate` was computed with
+    #[serde(default)]
+    pub aggregation: Aggregation,
+    /// outlier filter applied before aggregation
+    #[serde(default)]
+    pub outlier_filter: OutlierFilter,
+    /// volume weighted average price, weighted by last trade size
+    #[serde(default)]
+    pub vwap: Option<Decimal>,
+    /// statistics of every exchange that sent prices
+    #[serde(default)]
+    pub by_exchange: HashMap<String, ExchangeStats>,
+    /// spread between the exchange means
+    #[serde(default)]
+    pub spread: Option<Spread>,
+    /// consolidated best bid and offer across exchanges
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bbo: Option<Bbo>,
+    /// order book depth, when the depth channel is subscribed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<Depth>,
+    /// every public trade, when the trades channel is subscribed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trades: Vec<Trade>,
+    /// OHLC bars of the accepted prices, when a bar interval is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bars: Option<Bars>,
+    /// closed candles of every exchange, when the candle channel is subscribed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub candles: Vec<Candle>,
+    /// legs of a synthetic pair, its prices are derived instead of listed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synthetic: Option<Synthetic>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// legs a synthetic pair is derived from, price = base leg / quote leg
+pub struct Synthetic {
+    /// base coin against the bridge coin, ex. sol_usdt of sol_eth
+    pub base_leg: Instrument,
+    /// quote coin against the bridge coin, ex. eth_usdt of sol_eth
+    pub quote_leg: Instrument,
+    /// legs older than this many milliseconds are not combined
+    pub max_staleness_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+/// leg prices a synthetic price was derived from
+pub struct Legs {
+    pub base: Decimal,
+    pub quote: Decimal,
+    /// age of the older leg in milliseconds when the legs were combined
+    pub age_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// OHLC bars of a pair at a fixed interval
+pub struct Bars {
+    /// bar length in milliseconds
+    pub interval_ms: u64,
+    /// bars over the prices of every exchange
+    pub composite: Vec<Bar>,
+    pub by_exchange: HashMap<String, Vec<Bar>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// OHLC bar, only intervals with prices get a bar
+pub struct Bar {
+    /// unix time in milliseconds the interval starts, aligned to the interval
+    pub start: u64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    /// sum of the last trade sizes in the interval
+    pub volume: Decimal,
+    /// number of prices in the interval
+    pub ticks: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// price statistics of one exchange for a pair
+pub struct ExchangeStats {
+    pub count: usize,
+    pub mean: Decimal,
+    pub min: Decimal,
+    pub max: Decimal,
+    pub last: Decimal,
+    /// population standard deviation
+    pub stddev: Decimal,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// spread between the highest and lowest exchange mean of a pair
+pub struct Spread {
+    /// exchange with the highest mean
+    pub high: String,
+    /// exchange with the lowest mean
+    pub low: String,
+    pub absolute: Decimal,
+    /// absolute spread in basis points of the mid of high and low
+    pub bps: Decimal,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 /// prices pairs structure
 pub struct PricesPairs {
     pub name: String,
-    pub price: f64,
+    /// exact decimal price, written with the precision the exchange sent
+    pub price: Decimal,
+    /// exchange event time, unix time in milliseconds
+    #[serde(default)]
+    pub event_time: Option<u64>,
+    /// local receive time, unix time in milliseconds
+    #[serde(default)]
+    pub received_at: u64,
+    /// local receive time, monotonic microseconds since `monotonic_epoch`
+    #[serde(default)]
+    pub received_mono: u64,
+    /// 24h traded volume in base coin
+    #[serde(default)]
+    pub volume_24h: Option<Decimal>,
+    /// 24h traded volume in quote coin
+    #[serde(default)]
+    pub quote_volume_24h: Option<Decimal>,
+    /// size of the last trade in base coin
+    #[serde(default)]
+    pub last_size: Option<Decimal>,
+    /// top of book sent with the price
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote: Option<Quote>,
+    /// reason the point was left out of the aggregation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected: Option<String>,
+    /// instrument the exchange listed, its quote may be an equivalent of the pair quote
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instrument: Option<Instrument>,
+    /// rate of the pair quote in the listed quote, the exchange price was divided by it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Decimal>,
+    /// legs of a synthetic price, none for prices the exchange listed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legs: Option<Legs>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-/// binanase socket response structure
-pub struct BinanceResponse {
-    pub s: String,
-    pub c: String,
+#[derive(Debug, Clone, PartialEq, Default)]
+/// normalized ticker update decoded from an exchange message
+pub struct Tick {
+    /// pair key without separator, ex. BTCUSDT
+    pub pair: String,
+    pub price: Decimal,
+    /// exchange event time, unix time in milliseconds
+    pub event_time: Option<u64>,
+    /// local receive time of the message, set by the handler
+    pub received_at: u64,
+    pub received_mono: u64,
+    pub volume_24h: Option<Decimal>,
+    pub quote_volume_24h: Option<Decimal>,
+    pub last_size: Option<Decimal>,
+    pub quote: Option<Quote>,
+    /// instrument the exchange listed, set by the handler
+    pub instrument: Option<Instrument>,
+    /// quote conversion rate applied by the handler
+    pub rate: Option<Decimal>,
+    /// legs of a synthetic tick derived by the handler
+    pub legs: Option<Legs>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-/// coinbase socket response structuer
-pub struct CoinbaseResponse {
-    pub product_id: String,
-    pub price: String,
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+/// best bid and ask of one exchange
+pub struct Quote {
+    pub bid: Decimal,
+    pub ask: Decimal,
+    #[serde(default)]
+    pub bid_size: Option<Decimal>,
+    #[serde(default)]
+    pub ask_size: Option<Decimal>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-/// okex socket response child structure
-pub struct OkexResponseChild {
-    #[serde(rename = "instId")]
-    pub inst_id: String,
-    pub last: String,
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// consolidated best bid and offer over the latest quote of every exchange
+pub struct Bbo {
+    pub bid: Decimal,
+    pub bid_size: Option<Decimal>,
+    pub bid_exchange: String,
+    pub ask: Decimal,
+    pub ask_size: Option<Decimal>,
+    pub ask_exchange: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-/// okex socket response parent structure
-pub struct OkexResponse {
-    pub data: Vec<OkexResponseChild>,
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+/// order book depth of a pair
+pub struct Depth {
+    /// bids of every exchange merged by price, highest first
+    pub bids: Vec<DepthLevel>,
+    /// asks of every exchange merged by price, lowest first
+    pub asks: Vec<DepthLevel>,
+    /// last top of the local book of every exchange
+    pub by_exchange: HashMap<String, BookDepth>,
 }
 
-pub type WSResult<T> = Result<T, WSError>;
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+/// top levels of a local order book
+pub struct BookDepth {
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+    /// exchange sequence number of the last applied update
+    pub sequence: Option<u64>,
+    /// local receive time of the last applied update, unix time in milliseconds
+    pub updated_at: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+/// price level of an order book
+pub struct DepthLevel {
+    pub price: Decimal,
+    pub size: Decimal,
+}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum SocketType {
-    Binance,
-    Okex,
-    Coinbase,
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// kind of an order book message
+pub enum BookAction {
+    /// full book, replaces the local book
+    Snapshot,
+    /// changed levels, size zero removes the level
+    Update,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum ResponseEnum {
-    Binance(BinanceResponse),
-    Okex(OkexResponse),
-    Coinbase(CoinbaseResponse),
+#[derive(Debug, Clone, PartialEq)]
+/// normalized order book message decoded from an exchange
+pub struct BookUpdate {
+    /// pair key without separator, ex. BTCUSDT
+    pub pair: String,
+    pub action: BookAction,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+    /// first sequence number covered by the update, binance `U`
+    pub first_sequence: Option<u64>,
+    /// last sequence number covered by the update, binance `u`, okx `seqId`
+    pub sequence: Option<u64>,
+    /// sequence number of the previous update, okx `prevSeqId`
+    pub prev_sequence: Option<u64>,
+    /// checksum of the book after the update, okx crc32
+    pub checksum: Option<i32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// local order book of one pair on one exchange
+pub struct OrderBook {
+    pub bids: BTreeMap<Decimal, Decimal>,
+    pub asks: BTreeMap<Decimal, Decimal>,
+    /// sequence number of the last applied update
+    pub sequence: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// public trade of an exchange
+pub struct Trade {
+    pub exchange: String,
+    /// pair key without separator, ex. BTCUSDT
+    pub pair: String,
+    pub price: Decimal,
+    pub size: Decimal,
+    /// side of the taker
+    pub side: TradeSide,
+    pub trade_id: String,
+    /// exchange trade time, unix time in milliseconds
+    pub ts: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+/// aggressor side of a trade
+pub enum TradeSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+/// exchange candle, in progress updates share the start of the candle
+pub struct Candle {
+    pub exchange: String,
+    /// pair key without separator, ex. BTCUSDT
+    pub pair: String,
+    /// unix time in milliseconds the candle opens
+    pub start: u64,
+    /// candle length in milliseconds
+    pub interval_ms: u64,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    /// traded volume in base coin
+    pub volume: Decimal,
+    /// the interval ended, the candle gets no more updates
+    pub closed: bool,
+    /// built from the ticker, the exchange has no candle channel for the interval
+    #[serde(default)]
+    pub resampled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// normalized update decoded from an exchange message
+pub enum Update {
+    Tick(Tick),
+    Book(BookUpdate),
+    Trade(Trade),
+    Candle(Candle),
+}
+
+impl Update {
+    /// pair of the update, the native symbol until the handler renames it
+    pub fn pair_mut(&mut self) -> &mut String {
+        match self {
+            Update::Tick(tick) => &mut tick.pair,
+            Update::Book(book) => &mut book.pair,
+            Update::Trade(trade) => &mut trade.pair,
+            Update::Candle(candle) => &mut candle.pair,
+        }
+    }
+
+    /// divide the prices by the rate of an equivalent quote, order books keep the listed
+    /// prices and their depth is converted instead
+    pub fn convert(&mut self, rate: Decimal) {
+        let convert = |price: Decimal| price.checked_div(rate).unwrap_or_default();
+        match self {
+            Update::Tick(tick) => {
+                tick.price = convert(tick.price);
+                tick.quote_volume_24h = tick.quote_volume_24h.map(convert);
+                if let Some(quote) = tick.quote.as_mut() {
+                    quote.bid = convert(quote.bid);
+                    quote.ask = convert(quote.ask);
+                }
+                tick.rate = Some(rate);
+            }
+            Update::Trade(trade) => trade.price = convert(trade.price),
+            Update::Candle(candle) => {
+                candle.open = convert(candle.open);
+                candle.high = convert(candle.high);
+                candle.low = convert(candle.low);
+                candle.close = convert(candle.close);
+            }
+            Update::Book(_) => {}
+        }
+    }
+}
+
+pub type WSResult<T> = Result<T, WSError>;
+
+#[derive(Debug)]
+/// event sent from a socket task to the collector
+pub enum SocketEvent {
+    /// the socket connected and subscribed, unix time in milliseconds
+    Subscribed {
+        name: String,
+        at: u64,
+    },
+    Ticks {
+        name: String,
+        ticks: Vec<Tick>,
+    },
+    Reconnect(Reconnect),
+    Trades {
+        name: String,
+        trades: Vec<Trade>,
+    },
+    Candles {
+        name: String,
+        candles: Vec<Candle>,
+    },
+    /// top of a local order book after it changed
+    Depth {
+        name: String,
+        pair: String,
+        depth: BookDepth,
+    },
+    /// a message could not be used, the socket keeps running
+    Degraded {
+        name: String,
+        reason: String,
+    },
+    /// the socket stopped for good
+    Error {
+        name: String,
+        error: WSError,
+    },
 }
 
 #[derive(Debug)]
 pub struct WSHandler {
     config: WebSocketConfig,
-    socket_type: SocketType,
+    exchange: Box<dyn Exchange>,
     pairs: Vec<String>,
+    reconnect: ReconnectConfig,
+    heartbeat: HeartbeatConfig,
+    channels: Channels,
+    /// native symbols of the pairs, decoded updates are renamed to the pair key
+    symbols: SymbolMap,
+    /// equivalent quote coins the symbols were listed with
+    quote_groups: Vec<Vec<String>>,
+    /// convert ticks of an equivalent quote at the live rate
+    convert_quotes: bool,
+    /// synthetic pairs derived from the ticks of their legs, keyed by pair
+    synthetics: HashMap<String, Synthetic>,
+    /// latest price and monotonic receive time of every pair of the socket, rate pairs
+    /// convert equivalent quotes and legs are combined into synthetic prices
+    last_prices: HashMap<String, (Decimal, u64)>,
+    /// local order books keyed by pair, rebuilt on every connect
+    books: HashMap<String, OrderBook>,
     pub socket_stream: Option<WebSocketStream<MaybeTlsStream<TcpStream>>>,
 }
 
 impl WSHandler {
-    pub fn new(config: &WebSocketConfig, socket_type: SocketType, pairs: Vec<String>) -> WSHandler {
+    pub fn new(
+        config: &WebSocketConfig,
+        exchange: Box<dyn Exchange>,
+        pairs: Vec<String>,
+    ) -> WSHandler {
         WSHandler {
             config: config.clone(),
-            socket_type,
+            symbols: SymbolMap::new(exchange.as_ref(), config, &pairs, &[]),
+            quote_groups: vec![],
+            convert_quotes: false,
+            synthetics: HashMap::new(),
+            last_prices: HashMap::new(),
+            exchange,
             pairs,
+            reconnect: ReconnectConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            channels: Channels::default(),
+            books: HashMap::new(),
             socket_stream: None,
         }
     }
 
+    /// set reconnect policy
+    pub fn with_reconnect(mut self, reconnect: ReconnectConfig) -> WSHandler {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// set heartbeat policy
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> WSHandler {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// set channels subscribed next to the ticker
+    pub fn with_channels(mut self, channels: Channels) -> WSHandler {
+        self.channels = channels;
+        self
+    }
+
+    /// list pairs with the preferred equivalent quote of the exchange, optionally converting
+    /// their prices at the rate subscribed next to them
+    pub fn with_quotes(mut self, groups: &[Vec<String>], convert: bool) -> WSHandler {
+        self.quote_groups = groups.to_vec();
+        self.convert_quotes = convert;
+        self.symbols = self.symbol_map();
+        self
+    }
+
+    /// derive synthetic pairs, their legs are subscribed next to the pairs
+    pub fn with_synthetics(mut self, synthetics: HashMap<String, Synthetic>) -> WSHandler {
+        for synthetic in synthetics.values() {
+            for leg in [&synthetic.base_leg, &synthetic.quote_leg] {
+                let pair = leg.to_string();
+                if !self.pairs.contains(&pair) {
+                    self.pairs.push(pair);
+                }
+            }
+        }
+        self.synthetics = synthetics;
+        self.symbols = self.symbol_map();
+        self
+    }
+
+    /// native symbols of the pairs with the quote settings of the handler
+    fn symbol_map(&self) -> SymbolMap {
+        let symbols = SymbolMap::new(
+            self.exchange.as_ref(),
+            &self.config,
+            &self.pairs,
+            &self.quote_groups,
+        );
+        if self.convert_quotes {
+            return symbols.with_rates(self.exchange.as_ref(), &self.config);
+        }
+        symbols
+    }
+
+    /// name used to label prices of this socket
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn exchange(&self) -> &dyn Exchange {
+        self.exchange.as_ref()
+    }
+
+    pub fn config(&self) -> &WebSocketConfig {
+        &self.config
+    }
+
+    /// pairs subscribed by the handler, legs included
+    pub fn pairs(&self) -> &[String] {
+        &self.pairs
+    }
+
+    /// pairs, legs and rates whose native symbol is not in the symbols the exchange lists
+    pub fn unlisted_pairs(&self, listed: &HashSet<String>) -> Vec<String> {
+        self.symbols.unlisted(listed)
+    }
+
+    /// stop subscribing the pairs
+    pub fn without_pairs(mut self, pairs: &[String]) -> WSHandler {
+        self.remove_pairs(pairs);
+        self
+    }
+
+    fn remove_pairs(&mut self, pairs: &[String]) {
+        self.pairs.retain(|pair| {
+            Instrument::parse(pair)
+                .is_some_and(|instrument| !pairs.contains(&instrument.to_string()))
+        });
+        self.symbols = self.symbol_map();
+    }
+
+    /// stop subscribing the pairs of a native symbol the exchange rejected, false once
+    /// nothing is left subscribed
+    fn remove_symbol(&mut self, symbol: &str) -> bool {
+        let pairs: Vec<String> = self
+            .symbols
+            .instruments(symbol)
+            .iter()
+            .map(|instrument| instrument.to_string())
+            .collect();
+        self.remove_pairs(&pairs);
+        !self.symbols.pairs().is_empty()
+    }
+
     /// connect to web socket
     pub async fn connect(&mut self) -> WSResult<()> {
-        match self.socket_type {
-            SocketType::Binance => {
-                let binance_ws_api: String =
-                    helpers::binance_req_url(&self.config.ws_base_url, &self.pairs);
-                let (binance_socket, _binance_response) = connect_async(binance_ws_api).await?;
-                self.socket_stream = Some(binance_socket);
+        let ws_api: String =
+            self.exchange
+                .connect_url(&self.config, self.symbols.pairs(), &self.channels);
+        let (socket, _response) = connect_async(ws_api).await?;
+        self.socket_stream = Some(socket);
+        Ok(())
+    }
+
+    /// subscribe web socket
+    pub async fn subscribe(&mut self) -> WSResult<()> {
+        let req_param: String =
+            self.exchange
+                .subscribe_message(&self.config, self.symbols.pairs(), &self.channels)?;
+        let socket = self
+            .socket_stream
+            .as_mut()
+            .expect("There is some issue in socket stream");
+        socket.send(Message::Text(req_param)).await?;
+        Ok(())
+    }
+
+    /// connect to web socket and subscribe the pairs
+    pub async fn connect_and_subscribe(&mut self) -> WSResult<()> {
+        self.connect().await?;
+        self.subscribe().await?;
+        self.books.clear();
+        if self.channels.depth.is_some() {
+            self.fetch_book_snapshots().await?;
+        }
+        Ok(())
+    }
+
+    /// fetch the rest order book snapshots of exchanges sending none on subscribe
+    async fn fetch_book_snapshots(&mut self) -> WSResult<()> {
+        for symbol in self.symbols.natives() {
+            let Some(url) = self.exchange.book_snapshot_url(&self.config, &symbol)? else {
+                continue;
+            };
+            let body: Value = helpers::http_client()?
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let snapshot = Update::Book(self.exchange.parse_book_snapshot(&symbol, body)?);
+            for snapshot in self.symbols.normalize(snapshot) {
+                let Update::Book(snapshot) = snapshot else {
+                    continue;
+                };
+                let mut book = OrderBook::default();
+                book.apply(&snapshot)?;
+                self.books.insert(snapshot.pair, book);
             }
-            SocketType::Okex => {
-                let (okex_socket, _okex_response) = connect_async(&self.config.ws_base_url).await?;
-                self.socket_stream = Some(okex_socket);
+        }
+        Ok(())
+    }
+
+    /// wait for the next message and decode it into updates, None once the stream ends
+    pub async fn next_updates(&mut self) -> Option<WSResult<Vec<Update>>> {
+        let socket = self.socket_stream.as_mut()?;
+        let msg = socket.next().await?;
+        let (received_at, received_mono) = (helpers::now_millis(), helpers::monotonic_micros());
+        let updates = match msg {
+            // server closing the socket ends the stream
+            Ok(Message::Close(_)) => return None,
+            // tungstenite queues the pong reply, flush so it is sent right away
+            Ok(Message::Ping(_)) => socket.flush().await.map(|_| vec![]).map_err(WSError::from),
+            msg => parser::message_parser(self.exchange.as_ref(), self.config.compression, msg),
+        };
+        Some(updates.map(|updates| {
+            let updates: Vec<Update> = updates
+                .into_iter()
+                .flat_map(|update| self.symbols.normalize(update))
+                .collect();
+            let mut normalized = vec![];
+            for mut update in updates {
+                let listing = self
+                    .symbols
+                    .listing(update.pair_mut())
+                    .map(|(instrument, listed)| (instrument.clone(), listed.clone()));
+                if let Update::Tick(tick) = &mut update {
+                    tick.received_at = received_at;
+                    tick.received_mono = received_mono;
+                }
+                if let Some((instrument, listed)) = listing {
+                    if self.converts(&instrument, &listed) && !matches!(update, Update::Book(_)) {
+                        // updates arriving before the first rate are skipped
+                        let Some(rate) = self.quote_rate(&instrument, &listed) else {
+                            continue;
+                        };
+                        update.convert(rate);
+                    }
+                    if let Update::Tick(tick) = &mut update {
+                        tick.instrument = Some(listed);
+                    }
+                }
+                let mut synthetic = vec![];
+                match &update {
+                    Update::Tick(tick) => {
+                        self.last_prices
+                            .insert(tick.pair.clone(), (tick.price, tick.received_mono));
+                        synthetic = self.synthetic_ticks(tick);
+                    }
+                    // the candle socket has no ticker, its rates come from the rate candles
+                    Update::Candle(candle) if self.channels.candles_only => {
+                        self.last_prices
+                            .insert(candle.pair.clone(), (candle.close, received_mono));
+                    }
+                    _ => {}
+                }
+                normalized.push(update);
+                normalized.extend(synthetic.into_iter().map(Update::Tick));
             }
-            SocketType::Coinbase => {
-                let (coinbase_socket, _coinbase_response) =
-                    connect_async(&self.config.ws_base_url).await?;
-                self.socket_stream = Some(coinbase_socket);
+            normalized
+        }))
+    }
+
+    /// prices of the pair are listed with an equivalent quote and converted
+    fn converts(&self, instrument: &Instrument, listed: &Instrument) -> bool {
+        self.convert_quotes && instrument.quote != listed.quote
+    }
+
+    /// latest rate of the listed quote in the pair quote, none before it arrived
+    fn quote_rate(&self, instrument: &Instrument, listed: &Instrument) -> Option<Decimal> {
+        let rate_key = format!("{}{}", instrument.quote, listed.quote);
+        self.last_prices
+            .get(&rate_key)
+            .map(|&(rate, _)| rate)
+            .filter(|rate| !rate.is_zero())
+    }
+
+    /// synthetic ticks of the pairs the tick is a leg of, when the other leg is fresh enough
+    fn synthetic_ticks(&self, tick: &Tick) -> Vec<Tick> {
+        let mut ticks = vec![];
+        for (pair, synthetic) in &self.synthetics {
+            let (base_key, quote_key) = (synthetic.base_leg.key(), synthetic.quote_leg.key());
+            if tick.pair != base_key && tick.pair != quote_key {
+                continue;
+            }
+            let (Some(&(base, base_mono)), Some(&(quote, quote_mono))) = (
+                self.last_prices.get(&base_key),
+                self.last_prices.get(&quote_key),
+            ) else {
+                continue;
+            };
+            let age_ms = tick.received_mono.saturating_sub(base_mono.min(quote_mono)) / 1000;
+            if age_ms > synthetic.max_staleness_ms {
+                continue;
             }
+            let Some(price) = base.checked_div(quote) else {
+                continue;
+            };
+            ticks.push(Tick {
+                pair: pair.clone(),
+                price,
+                event_time: tick.event_time,
+                received_at: tick.received_at,
+                received_mono: tick.received_mono,
+                legs: Some(Legs {
+                    base,
+                    quote,
+                    age_ms,
+                }),
+                ..Default::default()
+            });
         }
-        Ok(())
+        ticks
     }
 
-    /// subscribe web socket
-    pub async fn subscribe(&mut self) -> WSResult<()> {
-        match self.socket_type {
-            SocketType::Binance => {
-                let binance_socket = self
-                    .socket_stream
-                    .as_mut()
-                    .expect("There is some issue in binance socket");
-                let (mut _binance_write, _) = binance_socket.split();
-                let binance_req_param: String = helpers::create_req_params(
-                    SocketType::Binance,
-                    &self.config.req_param,
-                    &self.pairs,
-                )?;
-                _binance_write
-                    .send(Message::Text(binance_req_param))
-                    .await?;
-            }
-            SocketType::Okex => {
-                let okex_socket = self
-                    .socket_stream
-                    .as_mut()
-                    .expect("There is some issue in okex socket");
-                let okex_req_param: String = helpers::create_req_params(
-                    SocketType::Okex,
-                    &self.config.req_param,
-                    &self.pairs,
-                )?;
-                let (mut okex_write, _) = okex_socket.split();
-                okex_write.send(Message::Text(okex_req_param)).await?;
-            }
-            SocketType::Coinbase => {
-                let coinbase_socket = self
-                    .socket_stream
-                    .as_mut()
-                    .expect("There is some issue in binance socket");
-                let coinbase_req_param: String = helpers::create_req_params(
-                    SocketType::Coinbase,
-                    &self.config.req_param,
-                    &self.pairs,
-                )?;
-                let (mut coinbase_write, _) = coinbase_socket.split();
-                coinbase_write
-                    .send(Message::Text(coinbase_req_param))
-                    .await?;
+    /// wait for the next updates, sending keepalives while the socket is idle and failing
+    /// when nothing arrives within the stale timeout
+    pub async fn next_updates_alive(&mut self) -> Option<WSResult<Vec<Update>>> {
+        let stale = time::sleep(self.heartbeat.stale_timeout);
+        tokio::pin!(stale);
+        loop {
+            let keepalive = time::sleep(self.heartbeat.keepalive_interval);
+            tokio::select! {
+                updates = self.next_updates() => return updates,
+                _ = keepalive, if self.exchange.keepalive_message().is_some() => {
+                    if let Err(e) = self.send_keepalive().await {
+                        return Some(Err(e));
+                    }
+                }
+                _ = &mut stale => {
+                    return Some(Err(WSError::StaleConnection(self.heartbeat.stale_timeout)));
+                }
             }
         }
+    }
+
+    /// send the exchange keepalive message
+    async fn send_keepalive(&mut self) -> WSResult<()> {
+        let (Some(socket), Some(message)) = (
+            self.socket_stream.as_mut(),
+            self.exchange.keepalive_message(),
+        ) else {
+            return Ok(());
+        };
+        socket.send(Message::Text(message)).await?;
         Ok(())
     }
+
+    /// connect and subscribe again with jittered exponential backoff
+    pub async fn reconnect(&mut self, reason: String) -> Reconnect {
+        self.socket_stream = None;
+        let mut reconnect = Reconnect {
+            name: self.name().to_string(),
+            reason,
+            disconnected_at: helpers::now_millis(),
+            reconnected_at: None,
+            attempts: 0,
+        };
+
+        while reconnect.attempts < self.reconnect.max_attempts {
+            time::sleep(helpers::reconnect_backoff(
+                &self.reconnect,
+                reconnect.attempts,
+            ))
+            .await;
+            reconnect.attempts += 1;
+
+            match self.connect_and_subscribe().await {
+                Ok(()) => {
+                    reconnect.reconnected_at = Some(helpers::now_millis());
+                    println!(
+                        "Exchange {} reconnected after {} attempts",
+                        reconnect.name, reconnect.attempts
+                    );
+                    return reconnect;
+                }
+                Err(e) => eprintln!(
+                    "Exchange {} reconnect attempt {} failed: {}",
+                    reconnect.name, reconnect.attempts, e
+                ),
+            }
+        }
+        reconnect
+    }
+
+    /// send close frame and drop the socket
+    pub async fn close(&mut self) {
+        if let Some(mut socket) = self.socket_stream.take() {
+            if let Err(e) = socket.close(None).await {
+                eprintln!("Exchange {} close failed: {}", self.name(), e);
+            }
+        }
+    }
+
+    /// send ticks and changed order books to the collector, false once the collector is gone
+    fn send_updates(
+        &mut self,
+        updates: Vec<Update>,
+        events: &mpsc::UnboundedSender<SocketEvent>,
+    ) -> WSResult<bool> {
+        let mut ticks = vec![];
+        let mut trades = vec![];
+        let mut candles = vec![];
+        let mut changed = vec![];
+        for update in updates {
+            match update {
+                Update::Tick(tick) => ticks.push(tick),
+                Update::Trade(trade) => trades.push(trade),
+                Update::Candle(candle) => candles.push(candle),
+                Update::Book(update) => {
+                    let book = self.books.entry(update.pair.clone()).or_default();
+                    if !book.apply(&update)? {
+                        continue;
+                    }
+                    if let (Some(expected), Some(checksum)) =
+                        (update.checksum, self.exchange.book_checksum(book))
+                    {
+                        if expected != checksum {
+                            return Err(WSError::BookOutOfSync(format!(
+                                "{} checksum {checksum} does not match {expected}",
+                                update.pair
+                            )));
+                        }
+                    }
+                    if !changed.contains(&update.pair) {
+                        changed.push(update.pair);
+                    }
+                }
+            }
+        }
+
+        let name = self.name().to_string();
+        if !ticks.is_empty()
+            && events
+                .send(SocketEvent::Ticks {
+                    name: name.clone(),
+                    ticks,
+                })
+                .is_err()
+        {
+            return Ok(false);
+        }
+        if !trades.is_empty()
+            && events
+                .send(SocketEvent::Trades {
+                    name: name.clone(),
+                    trades,
+                })
+                .is_err()
+        {
+            return Ok(false);
+        }
+        if !candles.is_empty()
+            && events
+                .send(SocketEvent::Candles {
+                    name: name.clone(),
+                    candles,
+                })
+                .is_err()
+        {
+            return Ok(false);
+        }
+        let levels = self.channels.depth.unwrap_or_default();
+        for pair in changed {
+            let mut depth = self.books[&pair].depth(levels, helpers::now_millis());
+            if let Some((instrument, listed)) = self.symbols.listing(&pair) {
+                if self.converts(instrument, listed) {
+                    let Some(rate) = self.quote_rate(instrument, listed) else {
+                        continue;
+                    };
+                    depth.convert(rate);
+                }
+            }
+            let event = SocketEvent::Depth {
+                name: name.clone(),
+                pair,
+                depth,
+            };
+            if events.send(event).is_err() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// read the socket and send events to the collector until shutdown is signalled
+    pub async fn read_loop(
+        mut self,
+        events: mpsc::UnboundedSender<SocketEvent>,
+        mut shutdown: watch::Receiver<bool>,
+    ) {
+        tokio::select! {
+            _ = self.read_events(&events) => {}
+            _ = shutdown.wait_for(|stop| *stop) => {}
+        }
+        self.close().await;
+    }
+
+    /// read the socket and send events to the collector, reconnecting when the stream drops
+    async fn read_events(&mut self, events: &mpsc::UnboundedSender<SocketEvent>) {
+        let name = self.name().to_string();
+        if self.socket_stream.is_none() {
+            if let Err(error) = self.connect_and_subscribe().await {
+                let _ = events.send(SocketEvent::Error { name, error });
+                return;
+            }
+        }
+        let subscribed = SocketEvent::Subscribed {
+            name: name.clone(),
+            at: helpers::now_millis(),
+        };
+        if events.send(subscribed).is_err() {
+            return;
+        }
+
+        loop {
+            let reason = match self.next_updates_alive().await {
+                // control frames and keepalive replies carry no updates
+                Some(Ok(updates)) if updates.is_empty() => continue,
+                Some(Ok(updates)) => match self.send_updates(updates, events) {
+                    Ok(true) => continue,
+                    Ok(false) => return,
+                    // a gap in the order book needs a fresh snapshot
+                    Err(e) => e.to_string(),
+                },
+                // transport errors and closed streams are recovered by reconnecting
+                Some(Err(WSError::TungsniteError(e))) => e.to_string(),
+                Some(Err(e @ WSError::StaleConnection(_))) => e.to_string(),
+                None => "stream closed".to_string(),
+                // a single rejected pair only degrades the exchange while other pairs stream
+                Some(Err(WSError::SubscriptionRejected(symbol, reason))) => {
+                    if !self.remove_symbol(&symbol) {
+                        let error = WSError::SubscriptionRejected(symbol, reason);
+                        let _ = events.send(SocketEvent::Error { name, error });
+                        return;
+                    }
+                    let event = SocketEvent::Degraded {
+                        name: name.to_string(),
+                        reason: format!("subscription {symbol} rejected: {reason}"),
+                    };
+                    if events.send(event).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                // exchange rejected the subscription, nothing more will arrive
+                Some(Err(error @ WSError::SocketResponseError(_))) => {
+                    let _ = events.send(SocketEvent::Error { name, error });
+                    return;
+                }
+                // a single bad message only degrades the exchange
+                Some(Err(error)) => {
+                    let event = SocketEvent::Degraded {
+                        name: name.to_string(),
+                        reason: error.to_string(),
+                    };
+                    if events.send(event).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            let reconnect = self.reconnect(reason).await;
+            let gave_up = reconnect.reconnected_at.is_none();
+            if events.send(SocketEvent::Reconnect(reconnect)).is_err() {
+                return;
+            }
+            if gave_up {
+                let error = WSError::ReconnectError(format!(
+                    "{} gave up after {} attempts",
+                    name, self.reconnect.max_attempts
+                ));
+                let _ = events.send(SocketEvent::Error { name, error });
+                return;
+            }
+        }
+    }
 }